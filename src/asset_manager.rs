@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+
+use crate::model::{Mesh, Model};
+use crate::sampler::Sampler;
+use crate::shader_error::ShaderError;
+use crate::shader_program::ShaderProgram;
+use crate::texture::{Texture, TextureOptions};
+
+/// A texture file and the options it was loaded with.
+pub type TextureKey = (PathBuf, TextureOptions);
+/// The file a mesh was loaded from and its index among the meshes there.
+pub type MeshKey = (PathBuf, usize);
+type ShaderKey = (&'static str, &'static str, Option<&'static str>);
+
+/// Assets shared between everything loaded, so that a texture or mesh used by
+/// several models is only uploaded once. Files are keyed by their canonical
+/// path.
+///
+/// Models, shader programs and samplers stay loaded until they are unloaded.
+/// Textures and meshes are only referenced weakly and are freed as soon as the
+/// last model using them is.
+pub struct AssetManager {
+    shader_cache_dir: PathBuf,
+    models: HashMap<PathBuf, Rc<Model>>,
+    meshes: HashMap<MeshKey, (Weak<Mesh>, usize)>,
+    textures: HashMap<TextureKey, (Weak<Texture>, usize)>,
+    shaders: HashMap<ShaderKey, Rc<ShaderProgram>>,
+    samplers: HashMap<TextureOptions, Rc<Sampler>>,
+}
+
+impl AssetManager {
+    /// Linked shader programs are cached on disk in `shader_cache_dir`.
+    pub fn new<P>(shader_cache_dir: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            shader_cache_dir: shader_cache_dir.into(),
+            models: HashMap::new(),
+            meshes: HashMap::new(),
+            textures: HashMap::new(),
+            shaders: HashMap::new(),
+            samplers: HashMap::new(),
+        }
+    }
+
+    pub fn model(&self, path: &Path) -> Option<Rc<Model>> {
+        self.models.get(&canonicalize(path)).cloned()
+    }
+
+    /// Adds a model loaded from `path`, which must be canonical. A model
+    /// already loaded from there is kept and returned instead.
+    pub fn insert_model(&mut self, path: PathBuf, model: Model) -> Rc<Model> {
+        self.prune();
+        Rc::clone(self.models.entry(path).or_insert_with(|| Rc::new(model)))
+    }
+
+    /// Drops the model loaded from `path`. It is freed, along with the meshes
+    /// and textures no other model uses, once nothing else holds on to it.
+    /// Returns `false` if no such model is loaded.
+    pub fn unload(&mut self, path: &Path) -> bool {
+        let removed = self.models.remove(&canonicalize(path)).is_some();
+        self.prune();
+        removed
+    }
+
+    pub fn mesh(&self, key: &MeshKey) -> Option<Rc<Mesh>> {
+        self.meshes.get(key).and_then(|(mesh, _)| mesh.upgrade())
+    }
+
+    /// Adds a mesh taking `size` bytes of GPU memory.
+    pub fn insert_mesh(&mut self, key: MeshKey, mesh: Mesh, size: usize) -> Rc<Mesh> {
+        let mesh = Rc::new(mesh);
+        self.meshes.insert(key, (Rc::downgrade(&mesh), size));
+        mesh
+    }
+
+    pub fn texture(&self, key: &TextureKey) -> Option<Rc<Texture>> {
+        self.textures
+            .get(key)
+            .and_then(|(texture, _)| texture.upgrade())
+    }
+
+    /// Adds a texture taking `size` bytes of GPU memory.
+    pub fn insert_texture(
+        &mut self,
+        key: TextureKey,
+        texture: Texture,
+        size: usize,
+    ) -> Rc<Texture> {
+        let texture = Rc::new(texture);
+        self.textures.insert(key, (Rc::downgrade(&texture), size));
+        texture
+    }
+
+    /// Returns the program built from the given sources, building it on first
+    /// use.
+    pub fn shader(
+        &mut self,
+        vertex_shader: &'static str,
+        fragment_shader: &'static str,
+        geometry_shader: Option<&'static str>,
+    ) -> Result<Rc<ShaderProgram>, ShaderError> {
+        let key = (vertex_shader, fragment_shader, geometry_shader);
+        if let Some(shader) = self.shaders.get(&key) {
+            return Ok(Rc::clone(shader));
+        }
+        let shader = Rc::new(ShaderProgram::new_cached(
+            &self.shader_cache_dir,
+            vertex_shader,
+            fragment_shader,
+            geometry_shader,
+        )?);
+        self.shaders.insert(key, Rc::clone(&shader));
+        Ok(shader)
+    }
+
+    /// Returns a sampler with the sampling options of `options`, creating it
+    /// on first use.
+    pub unsafe fn sampler(&mut self, options: &TextureOptions) -> Rc<Sampler> {
+        let sampler = self
+            .samplers
+            .entry(*options)
+            .or_insert_with(|| Rc::new(Sampler::new(options)));
+        Rc::clone(sampler)
+    }
+
+    /// Counts the loaded assets and the GPU memory taken by meshes and
+    /// textures.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mesh_sizes = live_sizes(self.meshes.values());
+        let texture_sizes = live_sizes(self.textures.values());
+        MemoryUsage {
+            models: self.models.len(),
+            meshes: mesh_sizes.len(),
+            mesh_bytes: mesh_sizes.iter().sum(),
+            textures: texture_sizes.len(),
+            texture_bytes: texture_sizes.iter().sum(),
+            shaders: self.shaders.len(),
+        }
+    }
+
+    /// Forgets the meshes and textures that have been freed.
+    fn prune(&mut self) {
+        self.meshes.retain(|_, (mesh, _)| mesh.strong_count() > 0);
+        self.textures
+            .retain(|_, (texture, _)| texture.strong_count() > 0);
+    }
+}
+
+/// Canonicalizes paths to existing files and keeps any others, such as the
+/// placeholder model's, as they are.
+fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Sizes of the assets that are still alive.
+fn live_sizes<'a, T: 'a>(entries: impl Iterator<Item = &'a (Weak<T>, usize)>) -> Vec<usize> {
+    entries
+        .filter(|(asset, _)| asset.strong_count() > 0)
+        .map(|(_, size)| *size)
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryUsage {
+    pub models: usize,
+    pub meshes: usize,
+    pub mesh_bytes: usize,
+    pub textures: usize,
+    pub texture_bytes: usize,
+    pub shaders: usize,
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MIB: f32 = 1024.0 * 1024.0;
+        write!(
+            f,
+            "{} models, {} meshes ({:.1} MiB), {} textures ({:.1} MiB), {} shaders",
+            self.models,
+            self.meshes,
+            self.mesh_bytes as f32 / MIB,
+            self.textures,
+            self.texture_bytes as f32 / MIB,
+            self.shaders
+        )
+    }
+}
@@ -0,0 +1,351 @@
+use std::f32::consts::PI;
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use gl::types::*;
+use nalgebra_glm as glm;
+use rand::prelude::*;
+
+use crate::buffer::Buffer;
+use crate::capabilities;
+use crate::model::Model;
+use crate::shader_error::ShaderError;
+use crate::shader_program::ShaderProgram;
+use crate::texture::Texture;
+use crate::texture_units::TextureUnits;
+
+const COMPUTE_SHADER: &str = include_str!("shaders/asteroids.comp");
+const WORK_GROUP_SIZE: usize = 256;
+
+/// Attribute location of the per-instance texture layer, after the model
+/// matrix in locations 3 to 6.
+const LAYER_LOCATION: u32 = 7;
+/// Sampler of the texture array in the layered fragment shader.
+const TEXTURE_ARRAY_SAMPLER: &str = "textures";
+
+const RING_RADIUS: f32 = 150.0;
+const RING_OFFSET: f32 = 25.0;
+/// Angular velocity in radians per second of an asteroid at `RING_RADIUS`.
+const ORBITAL_SPEED: f32 = 0.02;
+const MAX_SPIN_SPEED: f32 = 1.0;
+/// Seeds of the orbits and texture layers, fixed so that every run, and so
+/// every recording, shows the same asteroid field.
+const ORBIT_SEED: u64 = 0;
+const LAYER_SEED: u64 = 1;
+
+/// Orbital state of a single asteroid. The layout matches the `Orbit` struct
+/// in `asteroids.comp` under std430 rules.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Orbit {
+    radius: f32,
+    angle: f32,
+    height: f32,
+    angular_velocity: f32,
+    scale: f32,
+    spin: f32,
+    spin_velocity: f32,
+    padding: f32,
+}
+
+impl Orbit {
+    fn random<R: Rng>(rng: &mut R, angle: f32) -> Self {
+        let x = angle.sin() * RING_RADIUS + rng.gen_range(-RING_OFFSET, RING_OFFSET);
+        let z = angle.cos() * RING_RADIUS + rng.gen_range(-RING_OFFSET, RING_OFFSET);
+        let radius = (x * x + z * z).sqrt();
+        Self {
+            radius,
+            angle: x.atan2(z),
+            height: rng.gen_range(-RING_OFFSET, RING_OFFSET) * 0.4,
+            // Inner asteroids orbit faster, following Kepler's third law.
+            angular_velocity: ORBITAL_SPEED * (RING_RADIUS / radius).powf(1.5),
+            scale: rng.gen_range(0.05, 0.25),
+            spin: rng.gen_range(0.0, 2.0 * PI),
+            spin_velocity: rng.gen_range(-MAX_SPIN_SPEED, MAX_SPIN_SPEED),
+            padding: 0.0,
+        }
+    }
+
+    fn advance(&mut self, delta_time: f32) {
+        self.angle = (self.angle + self.angular_velocity * delta_time).rem_euclid(2.0 * PI);
+        self.spin = (self.spin + self.spin_velocity * delta_time).rem_euclid(2.0 * PI);
+    }
+
+    fn model_matrix(&self) -> glm::Mat4 {
+        let position = glm::vec3(
+            self.angle.sin() * self.radius,
+            self.height,
+            self.angle.cos() * self.radius,
+        );
+        let mut model = glm::translation(&position);
+        model = glm::scale(&model, &glm::vec3(self.scale, self.scale, self.scale));
+        glm::rotate(&model, self.spin, &glm::vec3(0.4, 0.6, 0.8))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Animation {
+    /// Orbits are advanced on the CPU and the model matrices are streamed to
+    /// the instance buffer every frame.
+    Cpu,
+    /// Orbits are advanced by a compute shader writing straight into the
+    /// instance buffer.
+    Gpu,
+}
+
+impl Animation {
+    /// Usage hint of the instance buffer, which the CPU streams to or the GPU
+    /// writes to every frame.
+    fn instance_usage(self) -> GLenum {
+        match self {
+            Animation::Cpu => gl::STREAM_DRAW,
+            Animation::Gpu => gl::DYNAMIC_COPY,
+        }
+    }
+}
+
+/// Time spent animating the asteroids on the CPU since the stats were last
+/// taken.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AnimationStats {
+    pub frames: u32,
+    pub update_time: Duration,
+    pub upload_time: Duration,
+}
+
+impl AnimationStats {
+    pub fn average_update_time(&self) -> Duration {
+        self.update_time / self.frames.max(1)
+    }
+
+    pub fn average_upload_time(&self) -> Duration {
+        self.upload_time / self.frames.max(1)
+    }
+}
+
+/// A ring of instanced asteroids orbiting the origin.
+pub struct AsteroidField {
+    model: Rc<Model>,
+    /// The current orbits when animated on the CPU, and the orbits as last
+    /// uploaded when animated on the GPU.
+    orbits: Vec<Orbit>,
+    /// Time the GPU has advanced the orbits by since they were uploaded.
+    gpu_elapsed: f32,
+    models: Vec<glm::Mat4>,
+    instance_buffer: Buffer,
+    /// The texture array layer of each asteroid.
+    layer_buffer: Buffer,
+    texture_array: Option<Rc<Texture>>,
+    orbit_buffer: Option<Buffer>,
+    compute_shader: Option<ShaderProgram>,
+    animation: Animation,
+    stats: AnimationStats,
+}
+
+impl AsteroidField {
+    /// Creates `count` asteroids. They are animated on the GPU when compute
+    /// shaders are available and on the CPU otherwise.
+    pub unsafe fn new(model: Rc<Model>, count: usize) -> Result<Self, ShaderError> {
+        let mut rng = StdRng::seed_from_u64(ORBIT_SEED);
+        let orbits: Vec<_> = (0..count)
+            .map(|i| Orbit::random(&mut rng, i as f32 / count as f32 * 2.0 * PI))
+            .collect();
+        let models: Vec<_> = orbits.iter().map(Orbit::model_matrix).collect();
+
+        let compute_shader = if capabilities::compute_shaders_supported() {
+            Some(ShaderProgram::new_compute(COMPUTE_SHADER)?)
+        } else {
+            None
+        };
+        let orbit_buffer = compute_shader
+            .as_ref()
+            .map(|_| Buffer::new(gl::SHADER_STORAGE_BUFFER, &orbits, gl::DYNAMIC_COPY));
+        let animation = match compute_shader {
+            Some(_) => Animation::Gpu,
+            None => Animation::Cpu,
+        };
+        let instance_buffer = Buffer::new(gl::ARRAY_BUFFER, &models, animation.instance_usage());
+        let layer_buffer = Buffer::new(gl::ARRAY_BUFFER, &vec![0u32; count], gl::STATIC_DRAW);
+        set_up_instancing(&model, &instance_buffer, &layer_buffer);
+
+        Ok(Self {
+            model,
+            orbits,
+            gpu_elapsed: 0.0,
+            models,
+            instance_buffer,
+            layer_buffer,
+            texture_array: None,
+            orbit_buffer,
+            compute_shader,
+            animation,
+            stats: AnimationStats::default(),
+        })
+    }
+
+    pub fn animation(&self) -> Animation {
+        self.animation
+    }
+
+    /// Switches between CPU and GPU animation, carrying the current orbits
+    /// over. Returns `false` if GPU animation is requested but unsupported.
+    pub unsafe fn set_animation(&mut self, animation: Animation) -> bool {
+        if animation == self.animation {
+            return true;
+        }
+        let orbit_buffer = match &self.orbit_buffer {
+            Some(orbit_buffer) => orbit_buffer,
+            None => return false,
+        };
+        match animation {
+            Animation::Cpu => {
+                gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+                orbit_buffer.read(gl::SHADER_STORAGE_BUFFER, &mut self.orbits);
+            }
+            Animation::Gpu => orbit_buffer.upload(gl::SHADER_STORAGE_BUFFER, &self.orbits),
+        }
+        self.instance_buffer
+            .set_usage(gl::ARRAY_BUFFER, animation.instance_usage());
+        self.gpu_elapsed = 0.0;
+        self.animation = animation;
+        true
+    }
+
+    /// Returns the CPU animation stats gathered since the last call.
+    pub fn take_stats(&mut self) -> AnimationStats {
+        mem::take(&mut self.stats)
+    }
+
+    pub unsafe fn update(&mut self, delta_time: f32) {
+        match self.animation {
+            Animation::Cpu => self.update_cpu(delta_time),
+            Animation::Gpu => self.update_gpu(delta_time),
+        }
+    }
+
+    unsafe fn update_cpu(&mut self, delta_time: f32) {
+        let start = Instant::now();
+        for (orbit, model) in self.orbits.iter_mut().zip(self.models.iter_mut()) {
+            orbit.advance(delta_time);
+            *model = orbit.model_matrix();
+        }
+        let uploading = Instant::now();
+        self.instance_buffer.upload(gl::ARRAY_BUFFER, &self.models);
+        let end = Instant::now();
+
+        self.stats.frames += 1;
+        self.stats.update_time += uploading - start;
+        self.stats.upload_time += end - uploading;
+    }
+
+    unsafe fn update_gpu(&mut self, delta_time: f32) {
+        if let (Some(shader), Some(orbit_buffer)) = (&self.compute_shader, &self.orbit_buffer) {
+            shader.use_program();
+            shader.set_uniform_uint("count", self.orbits.len() as u32);
+            shader.set_uniform_float("deltaTime", delta_time);
+            self.gpu_elapsed += delta_time;
+            orbit_buffer.bind_shader_storage(0);
+            self.instance_buffer.bind_shader_storage(1);
+            let groups = self.orbits.len().div_ceil(WORK_GROUP_SIZE);
+            gl::DispatchCompute(groups as u32, 1, 1);
+            gl::MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+        }
+    }
+
+    /// Draws every asteroid with `shader`, which is in use. With a texture
+    /// array set the shader samples the layer of each instance from its
+    /// `textures` array sampler, otherwise the material of the mesh.
+    pub unsafe fn draw(
+        &self,
+        shader: &ShaderProgram,
+        texture_units: &mut TextureUnits,
+    ) -> anyhow::Result<()> {
+        if let Some(texture_array) = &self.texture_array {
+            texture_units.bind_uniform(shader, TEXTURE_ARRAY_SAMPLER, texture_array, None)?;
+        }
+        for mesh in self.model.meshes.iter() {
+            if self.texture_array.is_none() {
+                mesh.bind_material(shader, texture_units, None)?;
+            }
+            mesh.draw_elements(self.orbits.len());
+        }
+        Ok(())
+    }
+
+    pub fn has_texture_array(&self) -> bool {
+        self.texture_array.is_some()
+    }
+
+    /// Gives each asteroid a random layer of `texture_array`, so that they
+    /// vary in appearance but are still drawn together.
+    pub unsafe fn set_texture_array(&mut self, texture_array: Rc<Texture>) {
+        let mut rng = StdRng::seed_from_u64(LAYER_SEED);
+        let layers: Vec<_> = (0..self.orbits.len())
+            .map(|_| rng.gen_range(0, texture_array.layers()))
+            .collect();
+        self.layer_buffer.upload(gl::ARRAY_BUFFER, &layers);
+        self.texture_array = Some(texture_array);
+    }
+
+    /// The asteroid model, whose meshes are set up to draw every asteroid as
+    /// an instance.
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// Replaces the asteroid model, e.g. once it has finished loading.
+    pub unsafe fn set_model(&mut self, model: Rc<Model>) {
+        set_up_instancing(&model, &self.instance_buffer, &self.layer_buffer);
+        self.model = model;
+    }
+
+    pub fn count(&self) -> usize {
+        self.orbits.len()
+    }
+
+    /// Returns the current model matrix of the asteroid at `index`. When
+    /// animated on the GPU it is worked out on the CPU from the uploaded orbit
+    /// rather than read back, which would wait for the compute shader, and
+    /// matches the GPU up to rounding.
+    pub fn instance_model_matrix(&self, index: usize) -> glm::Mat4 {
+        match self.animation {
+            Animation::Cpu => self.models[index],
+            Animation::Gpu => {
+                let mut orbit = self.orbits[index];
+                orbit.advance(self.gpu_elapsed);
+                orbit.model_matrix()
+            }
+        }
+    }
+}
+
+/// Sources the per-instance model matrices and texture layers of every mesh in
+/// `model` from `instance_buffer` and `layer_buffer`.
+unsafe fn set_up_instancing(model: &Model, instance_buffer: &Buffer, layer_buffer: &Buffer) {
+    for mesh in model.meshes.iter() {
+        gl::BindVertexArray(mesh.vao);
+        instance_buffer.bind(gl::ARRAY_BUFFER);
+        // A mat4 attribute occupies four consecutive vec4 locations.
+        for column in 0..4 {
+            let location = 3 + column;
+            gl::VertexAttribPointer(
+                location,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                mem::size_of::<glm::Mat4>() as GLsizei,
+                (column as usize * mem::size_of::<glm::Vec4>()) as *const c_void,
+            );
+            gl::EnableVertexAttribArray(location);
+            gl::VertexAttribDivisor(location, 1);
+        }
+        layer_buffer.bind(gl::ARRAY_BUFFER);
+        gl::VertexAttribIPointer(LAYER_LOCATION, 1, gl::UNSIGNED_INT, 0, ptr::null());
+        gl::EnableVertexAttribArray(LAYER_LOCATION);
+        gl::VertexAttribDivisor(LAYER_LOCATION, 1);
+    }
+    gl::BindVertexArray(0);
+}
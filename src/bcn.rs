@@ -0,0 +1,769 @@
+use crate::compressed_texture::CompressedFormat::{self, *};
+
+/// Interpolation weights out of 64 for 2, 3 and 4-bit indices.
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Subset of each texel for the two subset partitions, one bit per texel.
+const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// Subset of each texel for the three subset partitions, two bits per texel.
+const PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+/// Anchor texel of the second subset of the two subset partitions. The first
+/// subset's anchor is always texel 0.
+const ANCHORS_2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor texels of the second and third subsets of the three subset
+/// partitions.
+const ANCHORS_3: [[usize; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6,
+        8, 5, 15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8,
+        5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3,
+        15, 6, 10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15,
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+/// Decompresses one mipmap level into the texels described by
+/// `CompressedFormat::fallback_format`, for drivers that cannot sample the
+/// format.
+pub fn decompress(format: CompressedFormat, width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (_, _, _, texel_size) = format.fallback_format();
+    let blocks_x = width.div_ceil(4);
+    let block_count = blocks_x * height.div_ceil(4);
+    let mut pixels = vec![0; width * height * texel_size];
+    let mut texels = [0; 16 * 12];
+    let texels = &mut texels[..16 * texel_size];
+    let blocks = data.chunks_exact(format.block_size()).take(block_count);
+    for (i, block) in blocks.enumerate() {
+        match format {
+            Bc1 | Bc1Srgb => decode_colors(block, false, false, texels),
+            Bc1Alpha | Bc1AlphaSrgb => decode_colors(block, false, true, texels),
+            Bc2 | Bc2Srgb => {
+                decode_colors(&block[8..], true, false, texels);
+                let alpha = u64::from_le_bytes(block_bytes(&block[..8]));
+                for (j, texel) in texels.chunks_exact_mut(4).enumerate() {
+                    texel[3] = (alpha >> (4 * j) & 0xF) as u8 * 17;
+                }
+            }
+            Bc3 | Bc3Srgb => {
+                decode_colors(&block[8..], true, false, texels);
+                decode_channel(&block[..8], false, texels, 4, 3);
+            }
+            Bc4 | Bc4Signed => decode_channel(block, format == Bc4Signed, texels, 1, 0),
+            Bc5 | Bc5Signed => {
+                decode_channel(&block[..8], format == Bc5Signed, texels, 2, 0);
+                decode_channel(&block[8..], format == Bc5Signed, texels, 2, 1);
+            }
+            Bc6hUnsigned | Bc6hSigned => decode_bc6h(block, format == Bc6hSigned, texels),
+            Bc7 | Bc7Srgb => decode_bc7(block, texels),
+        }
+
+        let (block_x, block_y) = (i % blocks_x * 4, i / blocks_x * 4);
+        for y in 0..4.min(height - block_y) {
+            for x in 0..4.min(width - block_x) {
+                let source = (y * 4 + x) * texel_size;
+                let target = ((block_y + y) * width + block_x + x) * texel_size;
+                pixels[target..target + texel_size]
+                    .copy_from_slice(&texels[source..source + texel_size]);
+            }
+        }
+    }
+    pixels
+}
+
+fn block_bytes(bytes: &[u8]) -> [u8; 8] {
+    let mut array = [0; 8];
+    array.copy_from_slice(bytes);
+    array
+}
+
+/// Decodes a BC1 color block into RGBA texels. BC2 and BC3 color blocks
+/// always use four colors, while BC1 blocks switch to three colors and black
+/// when the first endpoint is not greater than the second. That black is
+/// transparent with `punch_through`.
+fn decode_colors(block: &[u8], four_colors: bool, punch_through: bool, texels: &mut [u8]) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let mix = |a: u32, b: u32, divisor: u32| {
+        let mut color = [0, 0, 0, 255];
+        for c in 0..3 {
+            color[c] = ((a * e0[c] as u32 + b * e1[c] as u32) / divisor) as u8;
+        }
+        color
+    };
+    let mut palette = [
+        [e0[0], e0[1], e0[2], 255],
+        [e1[0], e1[1], e1[2], 255],
+        [0; 4],
+        [0; 4],
+    ];
+    if four_colors || c0 > c1 {
+        palette[2] = mix(2, 1, 3);
+        palette[3] = mix(1, 2, 3);
+    } else {
+        palette[2] = mix(1, 1, 2);
+        palette[3] = [0, 0, 0, if punch_through { 0 } else { 255 }];
+    }
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, texel) in texels.chunks_exact_mut(4).enumerate() {
+        texel.copy_from_slice(&palette[(indices >> (2 * i) & 3) as usize]);
+    }
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let (r, g, b) = (color >> 11 & 31, color >> 5 & 63, color & 31);
+    [
+        (r << 3 | r >> 2) as u8,
+        (g << 2 | g >> 4) as u8,
+        (b << 3 | b >> 2) as u8,
+    ]
+}
+
+/// Decodes a BC4 block into channel `channel` of texels `stride` bytes apart.
+/// Signed values are stored as `i8`.
+fn decode_channel(block: &[u8], signed: bool, texels: &mut [u8], stride: usize, channel: usize) {
+    let (e0, e1, min, max) = if signed {
+        let endpoint = |value: u8| (value as i8).max(-127) as i32;
+        (endpoint(block[0]), endpoint(block[1]), -127, 127)
+    } else {
+        (block[0] as i32, block[1] as i32, 0, 255)
+    };
+    let mut palette = [e0, e1, 0, 0, 0, 0, min, max];
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as i32) * e0 + i as i32 * e1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as i32) * e0 + i as i32 * e1) / 5;
+        }
+    }
+    let mut indices = [0; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+    for (i, texel) in texels.chunks_exact_mut(stride).enumerate() {
+        texel[channel] = palette[(indices >> (3 * i) & 7) as usize] as u8;
+    }
+}
+
+/// Reads a block's bits from the least significant end.
+struct Bits {
+    bits: u128,
+    position: u32,
+}
+
+impl Bits {
+    fn new(block: &[u8]) -> Self {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(block);
+        Self {
+            bits: u128::from_le_bytes(bytes),
+            position: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1 << count) - 1);
+        self.position += count;
+        value
+    }
+}
+
+fn weight(index_bits: u32, index: u32) -> u32 {
+    match index_bits {
+        2 => WEIGHTS_2[index as usize],
+        3 => WEIGHTS_3[index as usize],
+        _ => WEIGHTS_4[index as usize],
+    }
+}
+
+fn subset(subsets: usize, partition: usize, texel: usize) -> usize {
+    match subsets {
+        1 => 0,
+        2 => (PARTITIONS_2[partition] >> texel & 1) as usize,
+        _ => (PARTITIONS_3[partition] >> (2 * texel) & 3) as usize,
+    }
+}
+
+fn is_anchor(subsets: usize, partition: usize, texel: usize) -> bool {
+    texel == 0
+        || match subsets {
+            2 => ANCHORS_2[partition] == texel,
+            3 => ANCHORS_3[0][partition] == texel || ANCHORS_3[1][partition] == texel,
+            _ => false,
+        }
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// A p-bit shared by all channels of each endpoint.
+    endpoint_p_bits: bool,
+    /// A p-bit shared by both endpoints of each subset.
+    shared_p_bits: bool,
+    index_bits: u32,
+    /// Bits of the separate alpha indices, or 0 if colors and alpha share
+    /// indices.
+    index_bits_2: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 3, index_bits_2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true, index_bits: 3, index_bits_2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, index_bits_2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, index_bits_2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, index_bits_2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, index_bits_2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_p_bits: true, shared_p_bits: false, index_bits: 4, index_bits_2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, index_bits_2: 0 },
+];
+
+/// Decodes a BC7 block into RGBA texels.
+fn decode_bc7(block: &[u8], texels: &mut [u8]) {
+    let mode_index = block[0].trailing_zeros();
+    if mode_index >= 8 {
+        // Reserved blocks decode to transparent black.
+        texels.iter_mut().for_each(|value| *value = 0);
+        return;
+    }
+    let mode = &BC7_MODES[mode_index as usize];
+    let mut bits = Bits::new(block);
+    bits.read(mode_index + 1);
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = bits.read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = bits.read(mode.alpha_bits);
+    }
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let mut p_bits = [0; 6];
+        if mode.endpoint_p_bits {
+            for p_bit in p_bits.iter_mut().take(endpoint_count) {
+                *p_bit = bits.read(1);
+            }
+        } else {
+            for subset in 0..mode.subsets {
+                let p_bit = bits.read(1);
+                p_bits[2 * subset] = p_bit;
+                p_bits[2 * subset + 1] = p_bit;
+            }
+        }
+        for (endpoint, p_bit) in endpoints.iter_mut().zip(p_bits.iter()) {
+            for value in endpoint.iter_mut() {
+                *value = *value << 1 | p_bit;
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for value in endpoint.iter_mut().take(3) {
+            *value = expand(*value, color_bits);
+        }
+        endpoint[3] = if alpha_bits > 0 {
+            expand(endpoint[3], alpha_bits)
+        } else {
+            255
+        };
+    }
+
+    let mut indices = [0; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.subsets, partition, texel) as u32;
+        *index = bits.read(mode.index_bits - anchor);
+    }
+    let mut alpha_indices = indices;
+    if mode.index_bits_2 > 0 {
+        for (texel, index) in alpha_indices.iter_mut().enumerate() {
+            let anchor = (texel == 0) as u32;
+            *index = bits.read(mode.index_bits_2 - anchor);
+        }
+    }
+    let (mut color_index_bits, mut alpha_index_bits) = (mode.index_bits, mode.index_bits);
+    if mode.index_bits_2 > 0 {
+        alpha_index_bits = mode.index_bits_2;
+    }
+    if index_selection == 1 {
+        std::mem::swap(&mut indices, &mut alpha_indices);
+        std::mem::swap(&mut color_index_bits, &mut alpha_index_bits);
+    }
+
+    for (i, texel) in texels.chunks_exact_mut(4).enumerate() {
+        let subset = subset(mode.subsets, partition, i);
+        let (e0, e1) = (endpoints[2 * subset], endpoints[2 * subset + 1]);
+        let color_weight = weight(color_index_bits, indices[i]);
+        let alpha_weight = weight(alpha_index_bits, alpha_indices[i]);
+        let mut color = [0; 4];
+        for channel in 0..4 {
+            let weight = if channel < 3 {
+                color_weight
+            } else {
+                alpha_weight
+            };
+            color[channel] = ((64 - weight) * e0[channel] + weight * e1[channel] + 32) >> 6;
+        }
+        if rotation > 0 {
+            color.swap(rotation as usize - 1, 3);
+        }
+        for (value, component) in texel.iter_mut().zip(color.iter()) {
+            *value = *component as u8;
+        }
+    }
+}
+
+/// Expands a `bits` wide value to 8 bits by repeating its high bits.
+fn expand(value: u32, bits: u32) -> u32 {
+    let value = value << (8 - bits);
+    value | value >> bits
+}
+
+// Endpoint fields of BC6H blocks: the two endpoints of the first subset, then
+// those of the second, with the partition last.
+const RW: usize = 0;
+const GW: usize = 1;
+const BW: usize = 2;
+const RX: usize = 3;
+const GX: usize = 4;
+const BX: usize = 5;
+const RY: usize = 6;
+const GY: usize = 7;
+const BY: usize = 8;
+const RZ: usize = 9;
+const GZ: usize = 10;
+const BZ: usize = 11;
+const PARTITION: usize = 12;
+
+struct Bc6hMode {
+    subsets: usize,
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    /// Field bits in block order as (field, first bit, last bit). Some modes
+    /// store bits from high to low.
+    layout: &'static [(usize, u32, u32)],
+}
+
+#[rustfmt::skip]
+const BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5], layout: &[
+        (GY, 4, 4), (BY, 4, 4), (BZ, 4, 4), (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 4),
+        (GZ, 4, 4), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 4), (BZ, 1, 1),
+        (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6], layout: &[
+        (GY, 5, 5), (GZ, 4, 4), (GZ, 5, 5), (RW, 0, 6), (BZ, 0, 0), (BZ, 1, 1), (BY, 4, 4),
+        (GW, 0, 6), (BY, 5, 5), (BZ, 2, 2), (GY, 4, 4), (BW, 0, 6), (BZ, 3, 3), (BZ, 5, 5),
+        (BZ, 4, 4), (RX, 0, 5), (GY, 0, 3), (GX, 0, 5), (GZ, 0, 3), (BX, 0, 5), (BY, 0, 3),
+        (RY, 0, 5), (RZ, 0, 5), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4], layout: &[
+        (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 4), (RW, 10, 10), (GY, 0, 3), (GX, 0, 3),
+        (GW, 10, 10), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 3), (BW, 10, 10), (BZ, 1, 1), (BY, 0, 3),
+        (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4], layout: &[
+        (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 3), (RW, 10, 10), (GZ, 4, 4), (GY, 0, 3),
+        (GX, 0, 4), (GW, 10, 10), (GZ, 0, 3), (BX, 0, 3), (BW, 10, 10), (BZ, 1, 1), (BY, 0, 3),
+        (RY, 0, 3), (BZ, 0, 0), (BZ, 2, 2), (RZ, 0, 3), (GY, 4, 4), (BZ, 3, 3), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5], layout: &[
+        (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 3), (RW, 10, 10), (BY, 4, 4), (GY, 0, 3),
+        (GX, 0, 3), (GW, 10, 10), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 4), (BW, 10, 10), (BY, 0, 3),
+        (RY, 0, 3), (BZ, 1, 1), (BZ, 2, 2), (RZ, 0, 3), (BZ, 4, 4), (BZ, 3, 3), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5], layout: &[
+        (RW, 0, 8), (BY, 4, 4), (GW, 0, 8), (GY, 4, 4), (BW, 0, 8), (BZ, 4, 4), (RX, 0, 4),
+        (GZ, 4, 4), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 4), (BZ, 1, 1),
+        (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5], layout: &[
+        (RW, 0, 7), (GZ, 4, 4), (BY, 4, 4), (GW, 0, 7), (BZ, 2, 2), (GY, 4, 4), (BW, 0, 7),
+        (BZ, 3, 3), (BZ, 4, 4), (RX, 0, 5), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0), (GZ, 0, 3),
+        (BX, 0, 4), (BZ, 1, 1), (BY, 0, 3), (RY, 0, 5), (RZ, 0, 5), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5], layout: &[
+        (RW, 0, 7), (BZ, 0, 0), (BY, 4, 4), (GW, 0, 7), (GY, 5, 5), (GY, 4, 4), (BW, 0, 7),
+        (GZ, 5, 5), (BZ, 4, 4), (RX, 0, 4), (GZ, 4, 4), (GY, 0, 3), (GX, 0, 5), (GZ, 0, 3),
+        (BX, 0, 4), (BZ, 1, 1), (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3),
+        (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6], layout: &[
+        (RW, 0, 7), (BZ, 1, 1), (BY, 4, 4), (GW, 0, 7), (BY, 5, 5), (GY, 4, 4), (BW, 0, 7),
+        (BZ, 5, 5), (BZ, 4, 4), (RX, 0, 4), (GZ, 4, 4), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0),
+        (GZ, 0, 3), (BX, 0, 5), (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3),
+        (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 2, transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6], layout: &[
+        (RW, 0, 5), (GZ, 4, 4), (BZ, 0, 0), (BZ, 1, 1), (BY, 4, 4), (GW, 0, 5), (GY, 5, 5),
+        (BY, 5, 5), (BZ, 2, 2), (GY, 4, 4), (BW, 0, 5), (GZ, 5, 5), (BZ, 3, 3), (BZ, 5, 5),
+        (BZ, 4, 4), (RX, 0, 5), (GY, 0, 3), (GX, 0, 5), (GZ, 0, 3), (BX, 0, 5), (BY, 0, 3),
+        (RY, 0, 5), (RZ, 0, 5), (PARTITION, 0, 4),
+    ] },
+    Bc6hMode { subsets: 1, transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10], layout: &[
+        (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 9), (GX, 0, 9), (BX, 0, 9),
+    ] },
+    Bc6hMode { subsets: 1, transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9], layout: &[
+        (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 8), (RW, 10, 10), (GX, 0, 8), (GW, 10, 10),
+        (BX, 0, 8), (BW, 10, 10),
+    ] },
+    Bc6hMode { subsets: 1, transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8], layout: &[
+        (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 7), (RW, 11, 10), (GX, 0, 7), (GW, 11, 10),
+        (BX, 0, 7), (BW, 11, 10),
+    ] },
+    Bc6hMode { subsets: 1, transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4], layout: &[
+        (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 3), (RW, 15, 10), (GX, 0, 3), (GW, 15, 10),
+        (BX, 0, 3), (BW, 15, 10),
+    ] },
+];
+
+/// Decodes a BC6H block into RGB texels of three `f32`s.
+fn decode_bc6h(block: &[u8], signed: bool, texels: &mut [u8]) {
+    let mut bits = Bits::new(block);
+    let mode_index = match bits.read(2) {
+        0 => 0,
+        1 => 1,
+        low => match bits.read(3) << 2 | low {
+            0b00010 => 2,
+            0b00110 => 3,
+            0b01010 => 4,
+            0b01110 => 5,
+            0b10010 => 6,
+            0b10110 => 7,
+            0b11010 => 8,
+            0b11110 => 9,
+            0b00011 => 10,
+            0b00111 => 11,
+            0b01011 => 12,
+            0b01111 => 13,
+            _ => {
+                // Reserved modes decode to black.
+                texels.iter_mut().for_each(|value| *value = 0);
+                return;
+            }
+        },
+    };
+    let mode = &BC6H_MODES[mode_index];
+
+    let mut fields = [0i32; 13];
+    for &(field, first, last) in mode.layout {
+        let mut bit = first;
+        loop {
+            fields[field] |= (bits.read(1) << bit) as i32;
+            if bit == last {
+                break;
+            }
+            if first < last {
+                bit += 1;
+            } else {
+                bit -= 1;
+            }
+        }
+    }
+    let partition = fields[PARTITION] as usize;
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0i32; 3]; 4];
+    for (i, endpoint) in endpoints.iter_mut().enumerate().take(endpoint_count) {
+        endpoint.copy_from_slice(&fields[i * 3..i * 3 + 3]);
+    }
+
+    let epb = mode.endpoint_bits;
+    if signed {
+        for value in endpoints[0].iter_mut() {
+            *value = sign_extend(*value, epb);
+        }
+    }
+    if mode.transformed || signed {
+        for endpoint in endpoints.iter_mut().take(endpoint_count).skip(1) {
+            for (value, &bits) in endpoint.iter_mut().zip(mode.delta_bits.iter()) {
+                *value = sign_extend(*value, bits);
+            }
+        }
+    }
+    if mode.transformed {
+        let mask = (1 << epb) - 1;
+        let base = endpoints[0];
+        for endpoint in endpoints.iter_mut().take(endpoint_count).skip(1) {
+            for channel in 0..3 {
+                endpoint[channel] = (base[channel] + endpoint[channel]) & mask;
+                if signed {
+                    endpoint[channel] = sign_extend(endpoint[channel], epb);
+                }
+            }
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for value in endpoint.iter_mut() {
+            *value = unquantize(*value, epb, signed);
+        }
+    }
+
+    let index_bits = if mode.subsets == 1 { 4 } else { 3 };
+    for (i, texel) in texels.chunks_exact_mut(12).enumerate() {
+        let anchor = is_anchor(mode.subsets, partition, i) as u32;
+        let weight = weight(index_bits, bits.read(index_bits - anchor)) as i32;
+        let subset = subset(mode.subsets, partition, i);
+        let (e0, e1) = (endpoints[2 * subset], endpoints[2 * subset + 1]);
+        for channel in 0..3 {
+            let value = ((64 - weight) * e0[channel] + weight * e1[channel] + 32) >> 6;
+            let value = half_to_f32(finish_unquantize(value, signed));
+            texel[channel * 4..channel * 4 + 4].copy_from_slice(&value.to_ne_bytes());
+        }
+    }
+}
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    value << shift >> shift
+}
+
+/// Scales an endpoint to the 16-bit range used for interpolation.
+fn unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if !signed {
+        if bits >= 15 || value == 0 {
+            value
+        } else if value == (1 << bits) - 1 {
+            0xFFFF
+        } else {
+            ((value << 16) + 0x8000) >> bits
+        }
+    } else {
+        if bits >= 16 || value == 0 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let magnitude = if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+}
+
+/// Scales an interpolated value to the bits of a half float.
+fn finish_unquantize(value: i32, signed: bool) -> u16 {
+    if !signed {
+        ((value * 31) >> 6) as u16
+    } else if value < 0 {
+        0x8000 | ((-value * 31) >> 5) as u16
+    } else {
+        ((value * 31) >> 5) as u16
+    }
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let exponent = (half >> 10 & 0x1F) as u32;
+    let mantissa = (half & 0x3FF) as u32;
+    let magnitude = match exponent {
+        0 => mantissa as f32 / (1 << 24) as f32,
+        31 if mantissa == 0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => f32::from_bits((exponent + 112) << 23 | mantissa << 13),
+    };
+    if half & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs fields into a 128-bit block from the least significant bit.
+    struct BlockWriter {
+        bits: u128,
+        position: u32,
+    }
+
+    impl BlockWriter {
+        fn new() -> Self {
+            Self {
+                bits: 0,
+                position: 0,
+            }
+        }
+
+        fn write(&mut self, value: u32, count: u32) -> &mut Self {
+            self.bits |= (value as u128) << self.position;
+            self.position += count;
+            self
+        }
+
+        fn finish(&self) -> [u8; 16] {
+            assert!(self.position <= 128);
+            self.bits.to_le_bytes()
+        }
+    }
+
+    fn texel_f32s(texel: &[u8]) -> Vec<f32> {
+        texel
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_a_solid_bc1_block() {
+        // Pure red and black endpoints, every texel using the first.
+        let block = [0x00, 0xF8, 0x00, 0x00, 0, 0, 0, 0];
+        let pixels = decompress(Bc1, 4, 4, &block);
+        assert_eq!(pixels.len(), 4 * 4 * 4);
+        for texel in pixels.chunks_exact(4) {
+            assert_eq!(texel, [255, 0, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn decodes_bc1_three_color_blocks() {
+        // The first endpoint is not greater than the second, so index 2 is
+        // halfway between them and index 3 is black.
+        let block = [0x00, 0x00, 0xFF, 0xFF, 0b1110_1110, 0, 0, 0];
+        let opaque = decompress(Bc1, 4, 4, &block);
+        assert_eq!(opaque[..8], [127, 127, 127, 255, 0, 0, 0, 255]);
+        let punch_through = decompress(Bc1Alpha, 4, 4, &block);
+        assert_eq!(punch_through[..8], [127, 127, 127, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_bc4_blocks() {
+        // Index 0 is the first endpoint and index 1 the second.
+        let block = [200, 100, 0b0000_1000, 0, 0, 0, 0, 0];
+        let pixels = decompress(Bc4, 4, 4, &block);
+        assert_eq!(pixels[..3], [200, 100, 200]);
+        // -128 is clamped to -127 like the hardware does.
+        let block = [0x80, 0x10, 0, 0, 0, 0, 0, 0];
+        let pixels = decompress(Bc4Signed, 4, 4, &block);
+        assert_eq!(pixels[0] as i8, -127);
+    }
+
+    #[test]
+    fn decodes_a_solid_bc7_block() {
+        // Mode 6: 7-bit RGBA endpoints with a p-bit each and 4-bit indices.
+        let mut block = BlockWriter::new();
+        block.write(1 << 6, 7);
+        for &value in [64, 64, 32, 32, 0, 0, 127, 127].iter() {
+            block.write(value, 7);
+        }
+        block.write(0, 2);
+        let pixels = decompress(Bc7, 4, 4, &block.finish());
+        for texel in pixels.chunks_exact(4) {
+            assert_eq!(texel, [128, 64, 0, 254]);
+        }
+    }
+
+    #[test]
+    fn interpolates_bc7_endpoints() {
+        let mut block = BlockWriter::new();
+        block.write(1 << 6, 7);
+        for &value in [0, 127, 0, 127, 0, 127, 127, 127].iter() {
+            block.write(value, 7);
+        }
+        block.write(0b10, 2);
+        // The anchor texel has a 3-bit index; the second texel takes the
+        // second endpoint.
+        block.write(0, 3).write(15, 4);
+        let pixels = decompress(Bc7, 4, 4, &block.finish());
+        assert_eq!(pixels[..8], [0, 0, 0, 254, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn decodes_reserved_bc7_blocks_as_transparent_black() {
+        let pixels = decompress(Bc7, 4, 4, &[0; 16]);
+        assert!(pixels.iter().all(|&value| value == 0));
+    }
+
+    #[test]
+    fn decodes_a_solid_bc6h_block() {
+        // Mode 10: one subset with untransformed 10-bit endpoints.
+        let mut block = BlockWriter::new();
+        block.write(0b00011, 5);
+        for _ in 0..3 {
+            block.write(0x3FF, 10);
+        }
+        let pixels = decompress(Bc6hUnsigned, 4, 4, &block.finish());
+        assert_eq!(pixels.len(), 4 * 4 * 12);
+        for texel in pixels.chunks_exact(12) {
+            assert_eq!(texel_f32s(texel), [65504.0; 3]);
+        }
+    }
+
+    #[test]
+    fn converts_half_floats() {
+        assert_eq!(half_to_f32(0x3C00), 1.0);
+        assert_eq!(half_to_f32(0xC000), -2.0);
+        assert_eq!(half_to_f32(0x0001), 2.0f32.powi(-24));
+        assert_eq!(half_to_f32(0x7C00), f32::INFINITY);
+        assert!(half_to_f32(0x7E00).is_nan());
+    }
+
+    #[test]
+    fn crops_partial_blocks() {
+        // A 5x5 image is 2x2 blocks of solid red, green, blue and white.
+        let colors: [u16; 4] = [0xF800, 0x07E0, 0x001F, 0xFFFF];
+        let data: Vec<u8> = colors
+            .iter()
+            .flat_map(|color| {
+                let [low, high] = color.to_le_bytes();
+                vec![low, high, 0, 0, 0, 0, 0, 0]
+            })
+            .collect();
+        let pixels = decompress(Bc1, 5, 5, &data);
+        assert_eq!(pixels.len(), 5 * 5 * 4);
+        let pixel = |x: usize, y: usize| &pixels[(y * 5 + x) * 4..(y * 5 + x) * 4 + 4];
+        assert_eq!(pixel(3, 3), [255, 0, 0, 255]);
+        assert_eq!(pixel(4, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(0, 4), [0, 0, 255, 255]);
+        assert_eq!(pixel(4, 4), [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+
+use gl::types::*;
+
+/// A GL buffer object. The same buffer may be bound to several targets, e.g. as
+/// a vertex attribute source and as a shader storage buffer.
+#[derive(Debug)]
+pub struct Buffer {
+    id: u32,
+    size: usize,
+    usage: GLenum,
+}
+
+impl Buffer {
+    pub unsafe fn new<T>(target: GLenum, data: &[T], usage: GLenum) -> Self {
+        let size = mem::size_of_val(data);
+        let mut id = 0;
+        gl::GenBuffers(1, &mut id);
+        gl::BindBuffer(target, id);
+        gl::BufferData(
+            target,
+            size as GLsizeiptr,
+            data.as_ptr() as *const c_void,
+            usage,
+        );
+        Self { id, size, usage }
+    }
+
+    /// Replaces the whole contents of the buffer. The old storage is orphaned
+    /// first so the driver does not have to wait for draws still reading it.
+    pub unsafe fn upload<T>(&self, target: GLenum, data: &[T]) {
+        assert_eq!(mem::size_of_val(data), self.size);
+        gl::BindBuffer(target, self.id);
+        gl::BufferData(target, self.size as GLsizeiptr, ptr::null(), self.usage);
+        gl::BufferSubData(
+            target,
+            0,
+            self.size as GLsizeiptr,
+            data.as_ptr() as *const c_void,
+        );
+    }
+
+    /// Changes the usage hint of the buffer. Its contents are undefined until
+    /// they are next uploaded or written.
+    pub unsafe fn set_usage(&mut self, target: GLenum, usage: GLenum) {
+        gl::BindBuffer(target, self.id);
+        gl::BufferData(target, self.size as GLsizeiptr, ptr::null(), usage);
+        self.usage = usage;
+    }
+
+    /// Copies the whole contents of the buffer back into `data`, blocking until
+    /// all pending writes to it have finished.
+    pub unsafe fn read<T>(&self, target: GLenum, data: &mut [T]) {
+        assert_eq!(mem::size_of_val(data), self.size);
+        gl::BindBuffer(target, self.id);
+        gl::GetBufferSubData(
+            target,
+            0,
+            self.size as GLsizeiptr,
+            data.as_mut_ptr() as *mut c_void,
+        );
+    }
+
+    pub unsafe fn bind(&self, target: GLenum) {
+        gl::BindBuffer(target, self.id);
+    }
+
+    /// Binds the buffer to `binding` of the indexed `SHADER_STORAGE_BUFFER`
+    /// target. Requires GL 4.3.
+    pub unsafe fn bind_shader_storage(&self, binding: u32) {
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, self.id);
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
@@ -1,104 +1,406 @@
 use nalgebra_glm as glm;
 
-const INITIAL_FOV: f32 = 45.0;
-const SPEED: f32 = 2.5;
-const SENSITIVITY: f32 = 0.05;
+pub const INITIAL_FOV: f32 = 45.0;
+const INITIAL_ORTHOGRAPHIC_SIZE: f32 = 10.0;
+/// Far plane used by orthographic projections when `far` is infinite.
+const MAX_ORTHOGRAPHIC_FAR: f32 = 10000.0;
+pub const SPRINT_MULTIPLIER: f32 = 4.0;
+pub const SLOW_MULTIPLIER: f32 = 0.25;
+const MIN_SPEED: f32 = 0.1;
+const MAX_SPEED: f32 = 1000.0;
+/// Speed factor applied per line of scrolling.
+const SPEED_SCROLL_FACTOR: f32 = 1.2;
+/// Below this speed a coasting camera comes to a stop.
+const STOP_SPEED: f32 = 0.001;
+/// Degrees of roll per second in free flight.
+const ROLL_SPEED: f32 = 90.0;
+const MAX_PITCH: f32 = 89.0;
 
+/// Common interface of the camera modes `main` can switch between.
+pub trait CameraController {
+    /// Moves in each direction by its weight from 0 to 1, which is 1 for keys
+    /// and how far the stick or trigger is pushed for gamepads.
+    fn move_(&mut self, directions: &[(CameraMotion, f32)], delta_time: f32);
+    fn look(&mut self, mouse_delta: (f32, f32), delta_time: f32);
+    fn zoom(&mut self, scroll_delta: f32);
+    fn view_matrix(&self) -> glm::Mat4;
+    fn position(&self) -> glm::Vec3;
+    fn front(&self) -> glm::Vec3;
+    fn projection(&self) -> &Projection;
+    fn projection_mut(&mut self) -> &mut Projection;
+
+    fn projection_matrix(&self) -> glm::Mat4 {
+        self.projection().matrix()
+    }
+
+    fn view_projection(&self) -> glm::Mat4 {
+        self.projection_matrix() * self.view_matrix()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+    /// Vertical field of view in degrees, used by perspective projections.
+    pub fov: f32,
+    /// Half the height of the view volume, used by orthographic projections.
+    pub size: f32,
+    pub orthographic: bool,
+    /// Width divided by height of the viewport.
+    pub aspect: f32,
+    pub near: f32,
+    /// May be `f32::INFINITY` to never clip distant geometry. That loses all
+    /// depth precision unless `reversed_z` is set as well.
+    pub far: f32,
+    /// Maps the near plane to depth 1 and the far plane to depth 0, which
+    /// spreads depth precision evenly over large scenes. Only correct after
+    /// `apply_depth_state`, see `capabilities::clip_control_supported`.
+    pub reversed_z: bool,
+}
+
+impl Projection {
+    pub fn perspective(fov: f32, aspect: f32, near: f32, far: f32) -> Self {
+        Self {
+            fov,
+            size: INITIAL_ORTHOGRAPHIC_SIZE,
+            orthographic: false,
+            aspect,
+            near,
+            far,
+            reversed_z: false,
+        }
+    }
+
+    pub fn matrix(&self) -> glm::Mat4 {
+        let (near, far) = (self.near, self.far);
+        if self.orthographic {
+            let far = far.min(MAX_ORTHOGRAPHIC_FAR);
+            let (top, right) = (self.size, self.size * self.aspect);
+            return if self.reversed_z {
+                glm::ortho_rh_zo(-right, right, -top, top, far, near)
+            } else {
+                glm::ortho(-right, right, -top, top, near, far)
+            };
+        }
+        let fov = self.fov.to_radians();
+        match (self.reversed_z, far.is_finite()) {
+            (false, true) => glm::perspective(self.aspect, fov, near, far),
+            (false, false) => glm::infinite_perspective_rh_no(self.aspect, fov, near),
+            (true, true) => glm::reversed_perspective_rh_zo(self.aspect, fov, near, far),
+            (true, false) => glm::reversed_infinite_perspective_rh_zo(self.aspect, fov, near),
+        }
+    }
+
+    /// Sets the depth range, depth test and depth clear value expected by this
+    /// projection.
+    pub unsafe fn apply_depth_state(&self) {
+        if self.reversed_z {
+            gl::ClipControl(gl::LOWER_LEFT, gl::ZERO_TO_ONE);
+            gl::DepthFunc(gl::GREATER);
+            gl::ClearDepth(0.0);
+        } else {
+            if gl::ClipControl::is_loaded() {
+                gl::ClipControl(gl::LOWER_LEFT, gl::NEGATIVE_ONE_TO_ONE);
+            }
+            gl::DepthFunc(gl::LESS);
+            gl::ClearDepth(1.0);
+        }
+    }
+
+    /// Zooms by changing the field of view, or the view volume size when
+    /// orthographic.
+    pub fn zoom(&mut self, scroll_delta: f32) {
+        if self.orthographic {
+            self.size *= 1.1f32.powf(scroll_delta);
+        } else {
+            self.fov = (self.fov + scroll_delta).clamp(1.0, INITIAL_FOV);
+        }
+    }
+}
+
+/// How a `Camera` responds to input. All fields may be changed at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionSettings {
+    /// Top speed in units per second.
+    pub speed: f32,
+    /// Units per second squared at which the camera approaches top speed.
+    pub acceleration: f32,
+    /// Rate per second at which the camera slows down once input stops.
+    pub drag: f32,
+    pub sprint_multiplier: f32,
+    pub slow_multiplier: f32,
+    /// Degrees of rotation per pixel of mouse motion.
+    pub sensitivity: f32,
+    /// Time constant in seconds of the exponential mouse smoothing, where 0
+    /// disables smoothing.
+    pub mouse_smoothing: f32,
+}
+
+impl Default for MotionSettings {
+    fn default() -> Self {
+        Self {
+            speed: 10.0,
+            acceleration: 40.0,
+            drag: 6.0,
+            sprint_multiplier: SPRINT_MULTIPLIER,
+            slow_multiplier: SLOW_MULTIPLIER,
+            sensitivity: 0.05,
+            mouse_smoothing: 0.02,
+        }
+    }
+}
+
+/// A free-flying first person camera. By default it yaws around the world up
+/// axis with pitch limited to ±89°. In free flight it instead rotates around
+/// its own axes without limits and can roll, like a spaceship.
 pub struct Camera {
     position: glm::Vec3,
-    front: glm::Vec3,
-    right: glm::Vec3,
-    up: glm::Vec3,
+    orientation: glm::Quat,
     world_up: glm::Vec3,
-    yaw: f32,
-    pitch: f32,
-    fov: f32,
+    free_flight: bool,
+    projection: Projection,
+    velocity: glm::Vec3,
+    smoothed_mouse_delta: (f32, f32),
+    settings: MotionSettings,
 }
 
 impl Camera {
-    pub fn new(position: glm::Vec3, world_up: glm::Vec3, yaw: f32, pitch: f32) -> Self {
-        let front = glm::normalize(&glm::vec3(
+    pub fn new(
+        position: glm::Vec3,
+        world_up: glm::Vec3,
+        yaw: f32,
+        pitch: f32,
+        projection: Projection,
+    ) -> Self {
+        let mut camera = Self {
+            position,
+            orientation: glm::quat_identity(),
+            world_up,
+            free_flight: false,
+            projection,
+            velocity: glm::vec3(0.0, 0.0, 0.0),
+            smoothed_mouse_delta: (0.0, 0.0),
+            settings: MotionSettings::default(),
+        };
+        camera.set_yaw_pitch(yaw, pitch);
+        camera
+    }
+
+    pub fn settings(&self) -> &MotionSettings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut MotionSettings {
+        &mut self.settings
+    }
+
+    /// Scales the top speed by a factor per line of scrolling, scrolling up
+    /// (negative `scroll_delta`) speeding up.
+    pub fn scale_speed(&mut self, scroll_delta: f32) {
+        let speed = self.settings.speed * SPEED_SCROLL_FACTOR.powf(-scroll_delta);
+        self.settings.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Moves the camera without passing through the space in between, also
+    /// stopping any motion.
+    pub fn set_position(&mut self, position: glm::Vec3) {
+        self.position = position;
+        self.velocity = glm::vec3(0.0, 0.0, 0.0);
+    }
+
+    pub fn orientation(&self) -> glm::Quat {
+        self.orientation
+    }
+
+    pub fn set_orientation(&mut self, orientation: glm::Quat) {
+        self.orientation = glm::quat_normalize(&orientation);
+    }
+
+    /// Returns the yaw and pitch in degrees, measured from the +X axis around
+    /// +Y as in `new`. Any roll is discarded.
+    pub fn yaw_pitch(&self) -> (f32, f32) {
+        let front = self.front();
+        let yaw = front.z.atan2(front.x).to_degrees();
+        let pitch = front.y.clamp(-1.0, 1.0).asin().to_degrees();
+        (yaw, pitch)
+    }
+
+    /// Points the camera along the given yaw and pitch in degrees, clearing
+    /// any roll.
+    pub fn set_yaw_pitch(&mut self, yaw: f32, pitch: f32) {
+        let front = glm::vec3(
             yaw.to_radians().cos() * pitch.to_radians().cos(),
             pitch.to_radians().sin(),
             yaw.to_radians().sin() * pitch.to_radians().cos(),
-        ));
-        let right = glm::normalize(&glm::cross(&front, &world_up));
-        let up = glm::normalize(&glm::cross(&right, &front));
-        Self {
-            position,
-            front,
-            right,
-            up,
-            world_up,
-            yaw,
-            pitch,
-            fov: INITIAL_FOV,
+        );
+        self.orientation = look_rotation(&front, &self.world_up);
+    }
+
+    pub fn free_flight(&self) -> bool {
+        self.free_flight
+    }
+
+    /// Leaving free flight levels the camera and brings the pitch back within
+    /// limits.
+    pub fn set_free_flight(&mut self, free_flight: bool) {
+        if self.free_flight && !free_flight {
+            let (yaw, pitch) = self.yaw_pitch();
+            self.set_yaw_pitch(yaw, pitch.clamp(-MAX_PITCH, MAX_PITCH));
         }
+        self.free_flight = free_flight;
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    pub fn up(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    /// Rotates around the camera's own axes, so the angles accumulate without
+    /// gimbal lock.
+    fn rotate_local(&mut self, yaw: f32, pitch: f32, roll: f32) {
+        let rotation = glm::quat_angle_axis(-yaw.to_radians(), &glm::vec3(0.0, 1.0, 0.0))
+            * glm::quat_angle_axis(pitch.to_radians(), &glm::vec3(1.0, 0.0, 0.0))
+            * glm::quat_angle_axis(roll.to_radians(), &glm::vec3(0.0, 0.0, -1.0));
+        self.set_orientation(self.orientation * rotation);
     }
+}
 
-    pub fn view_matrix(&self) -> glm::Mat4 {
-        glm::look_at(&self.position, &(self.position + self.front), &self.up)
+impl CameraController for Camera {
+    fn view_matrix(&self) -> glm::Mat4 {
+        glm::look_at(&self.position, &(self.position + self.front()), &self.up())
     }
 
-    pub fn move_(&mut self, directions: &[CameraMotion], delta_time: f32) {
-        let mut velocity = glm::vec3(0.0, 0.0, 0.0);
-        for d in directions {
+    /// Accelerates towards top speed in the pressed directions and coasts to a
+    /// stop under drag once they are released.
+    fn move_(&mut self, directions: &[(CameraMotion, f32)], delta_time: f32) {
+        let (front, right, up) = (self.front(), self.right(), self.up());
+        let mut direction = glm::vec3(0.0, 0.0, 0.0);
+        let mut roll = 0.0;
+        for &(d, weight) in directions {
             match d {
-                CameraMotion::Forward => velocity += self.front,
-                CameraMotion::Backward => velocity -= self.front,
-                CameraMotion::Right => velocity += self.right,
-                CameraMotion::Left => velocity -= self.right,
-                CameraMotion::Up => velocity += self.up,
-                CameraMotion::Down => velocity -= self.up,
+                CameraMotion::Forward => direction += front * weight,
+                CameraMotion::Backward => direction -= front * weight,
+                CameraMotion::Right => direction += right * weight,
+                CameraMotion::Left => direction -= right * weight,
+                CameraMotion::Up => direction += up * weight,
+                CameraMotion::Down => direction -= up * weight,
+                CameraMotion::RollRight => roll += weight,
+                CameraMotion::RollLeft => roll -= weight,
+                CameraMotion::Sprint | CameraMotion::Slow => {}
             }
         }
-        if velocity != glm::vec3(0.0, 0.0, 0.0) {
-            self.position += SPEED * velocity.normalize() * delta_time;
-        }
-    }
-
-    pub fn look(&mut self, mouse_delta: (f32, f32)) {
-        let (dx, dy) = mouse_delta;
-        self.yaw += SENSITIVITY * dx;
-        self.pitch += SENSITIVITY * dy;
-        if self.pitch > 89.0 {
-            self.pitch = 89.0;
+        let multiplier = speed_multiplier(
+            directions,
+            self.settings.sprint_multiplier,
+            self.settings.slow_multiplier,
+        );
+        if direction != glm::vec3(0.0, 0.0, 0.0) {
+            // Partly pushed sticks fly slower, but diagonals are no faster.
+            if direction.norm() > 1.0 {
+                direction.normalize_mut();
+            }
+            let target = direction * self.settings.speed * multiplier;
+            let change = target - self.velocity;
+            let max_change = self.settings.acceleration * multiplier * delta_time;
+            if change.norm() > max_change {
+                self.velocity += change.normalize() * max_change;
+            } else {
+                self.velocity = target;
+            }
+        } else {
+            self.velocity *= (-self.settings.drag * delta_time).exp();
+            if self.velocity.norm() < STOP_SPEED {
+                self.velocity = glm::vec3(0.0, 0.0, 0.0);
+            }
         }
-        if self.pitch < -89.0 {
-            self.pitch = -89.0;
+        self.position += self.velocity * delta_time;
+        if self.free_flight && roll != 0.0 {
+            self.rotate_local(0.0, 0.0, ROLL_SPEED * roll * delta_time);
         }
-        self.front = glm::normalize(&glm::vec3(
-            self.yaw.to_radians().cos() * self.pitch.to_radians().cos(),
-            self.pitch.to_radians().sin(),
-            self.yaw.to_radians().sin() * self.pitch.to_radians().cos(),
-        ));
-        self.right = glm::normalize(&glm::cross(&self.front, &self.world_up));
-        self.up = glm::normalize(&glm::cross(&self.right, &self.front));
     }
 
-    pub fn zoom(&mut self, scroll_delta: f32) {
-        self.fov += scroll_delta;
-        if self.fov > 45.0 {
-            self.fov = 45.0;
-        }
-        if self.fov < 1.0 {
-            self.fov = 1.0;
+    fn look(&mut self, mouse_delta: (f32, f32), delta_time: f32) {
+        let smoothing = self.settings.mouse_smoothing;
+        let (dx, dy) = if smoothing > 0.0 {
+            let t = 1.0 - (-delta_time / smoothing).exp();
+            let (sx, sy) = self.smoothed_mouse_delta;
+            let smoothed = (sx + (mouse_delta.0 - sx) * t, sy + (mouse_delta.1 - sy) * t);
+            self.smoothed_mouse_delta = smoothed;
+            smoothed
+        } else {
+            mouse_delta
+        };
+        let sensitivity = self.settings.sensitivity;
+        if self.free_flight {
+            self.rotate_local(sensitivity * dx, sensitivity * dy, 0.0);
+            return;
         }
+        let (yaw, pitch) = self.yaw_pitch();
+        let pitch = (pitch + sensitivity * dy).clamp(-MAX_PITCH, MAX_PITCH);
+        self.set_yaw_pitch(yaw + sensitivity * dx, pitch);
     }
 
-    pub fn fov(&self) -> f32 {
-        self.fov
+    fn zoom(&mut self, scroll_delta: f32) {
+        self.projection.zoom(scroll_delta);
     }
 
-    pub fn position(&self) -> glm::Vec3 {
+    fn position(&self) -> glm::Vec3 {
         self.position
     }
 
-    pub fn front(&self) -> glm::Vec3 {
-        self.front
+    fn front(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 0.0, -1.0))
+    }
+
+    fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    fn projection_mut(&mut self) -> &mut Projection {
+        &mut self.projection
+    }
+}
+
+/// Returns the rotation that turns a camera looking down -Z with +Y up into one
+/// looking along `front`. A zero `front` keeps looking down -Z, and when
+/// `front` is parallel to `up` the roll is picked arbitrarily.
+pub fn look_rotation(front: &glm::Vec3, up: &glm::Vec3) -> glm::Quat {
+    if glm::length2(front) < f32::EPSILON {
+        return glm::quat_identity();
+    }
+    let back = -glm::normalize(front);
+    let mut right = glm::cross(up, &back);
+    if glm::length2(&right) < f32::EPSILON {
+        // Any axis not parallel to `back` gives a valid right vector.
+        let axis = if back.x.abs() < 0.9 {
+            glm::vec3(1.0, 0.0, 0.0)
+        } else {
+            glm::vec3(0.0, 0.0, 1.0)
+        };
+        right = glm::cross(&axis, &back);
+    }
+    let right = glm::normalize(&right);
+    let up = glm::cross(&back, &right);
+    glm::mat3_to_quat(&glm::Mat3::from_columns(&[right, up, back]))
+}
+
+/// Returns the speed multiplier of the `Sprint` and `Slow` modifiers in
+/// `directions`, blending towards `sprint` and `slow` by their weights.
+pub fn speed_multiplier(directions: &[(CameraMotion, f32)], sprint: f32, slow: f32) -> f32 {
+    let mut multiplier = 1.0;
+    for &(d, weight) in directions {
+        match d {
+            CameraMotion::Sprint => multiplier *= 1.0 + (sprint - 1.0) * weight,
+            CameraMotion::Slow => multiplier *= 1.0 + (slow - 1.0) * weight,
+            _ => {}
+        }
     }
+    multiplier
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CameraMotion {
     Forward,
     Backward,
@@ -106,4 +408,9 @@ pub enum CameraMotion {
     Right,
     Up,
     Down,
+    RollLeft,
+    RollRight,
+    /// Modifiers scaling the speed of the other motions.
+    Sprint,
+    Slow,
 }
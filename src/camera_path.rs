@@ -0,0 +1,498 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use nalgebra_glm as glm;
+
+use crate::camera::{self, Camera, CameraController};
+
+/// Arc length samples taken per spline segment to move at constant speed.
+const SAMPLES_PER_SEGMENT: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub position: glm::Vec3,
+    pub orientation: glm::Quat,
+}
+
+impl CameraPose {
+    pub fn of(camera: &Camera) -> Self {
+        Self {
+            position: camera.position(),
+            orientation: camera.orientation(),
+        }
+    }
+}
+
+/// Something a camera can follow over time.
+pub trait CameraPath {
+    /// Length of the path in seconds.
+    fn duration(&self) -> f32;
+    /// Returns the pose at `time`, clamped to the ends of the path, or `None`
+    /// if the path is empty.
+    fn sample(&self, time: f32) -> Option<CameraPose>;
+}
+
+/// Camera poses recorded every frame, stored as one `time position
+/// orientation` line per frame.
+#[derive(Debug, Default)]
+pub struct CameraRecording {
+    frames: Vec<(f32, CameraPose)>,
+}
+
+impl CameraRecording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a frame. Times must not decrease.
+    pub fn push(&mut self, time: f32, pose: CameraPose) {
+        self.frames.push((time, pose));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::parse(&fs::read_to_string(path.as_ref())?)
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let text = self.to_text()?;
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let frames = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let values = parse_floats(line)
+                    .filter(|values| values.len() == 8)
+                    .ok_or_else(|| anyhow!("invalid camera frame on line {}", i + 1))?;
+                let pose = CameraPose {
+                    position: glm::vec3(values[1], values[2], values[3]),
+                    orientation: glm::quat(values[4], values[5], values[6], values[7]),
+                };
+                Ok((values[0], pose))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { frames })
+    }
+
+    fn to_text(&self) -> Result<String> {
+        let mut text = String::new();
+        for (time, pose) in self.frames.iter() {
+            let (p, q) = (pose.position, pose.orientation.coords);
+            writeln!(
+                text,
+                "{} {} {} {} {} {} {} {}",
+                time, p.x, p.y, p.z, q.x, q.y, q.z, q.w
+            )?;
+        }
+        Ok(text)
+    }
+}
+
+impl CameraPath for CameraRecording {
+    fn duration(&self) -> f32 {
+        match (self.frames.first(), self.frames.last()) {
+            (Some((start, _)), Some((end, _))) => end - start,
+            _ => 0.0,
+        }
+    }
+
+    fn sample(&self, time: f32) -> Option<CameraPose> {
+        let &(start, first) = self.frames.first()?;
+        let time = start + time;
+        let next = self.frames.iter().position(|&(t, _)| t > time);
+        let pose = match next {
+            None => self.frames[self.frames.len() - 1].1,
+            Some(0) => first,
+            Some(i) => {
+                let (t0, a) = self.frames[i - 1];
+                let (t1, b) = self.frames[i];
+                let s = (time - t0) / (t1 - t0);
+                CameraPose {
+                    position: glm::lerp(&a.position, &b.position, s),
+                    orientation: glm::quat_slerp(&a.orientation, &b.orientation, s),
+                }
+            }
+        };
+        Some(pose)
+    }
+}
+
+/// A Catmull-Rom spline through authored points, followed at a constant speed
+/// while looking along the direction of travel.
+#[derive(Debug)]
+pub struct SplinePath {
+    points: Vec<glm::Vec3>,
+    world_up: glm::Vec3,
+    speed: f32,
+    /// Cumulative arc length at `SAMPLES_PER_SEGMENT` evenly spaced spline
+    /// parameters per segment.
+    lengths: Vec<f32>,
+}
+
+impl SplinePath {
+    /// Creates a path through `points` travelled at `speed` units per second.
+    /// At least two points are required, and they must be finite.
+    pub fn new(points: Vec<glm::Vec3>, world_up: glm::Vec3, speed: f32) -> Result<Self> {
+        if points.len() < 2 {
+            return Err(anyhow!("a spline path needs at least two points"));
+        }
+        if points
+            .iter()
+            .any(|point| point.iter().any(|v| !v.is_finite()))
+        {
+            return Err(anyhow!("spline points must be finite"));
+        }
+        if !(speed > 0.0 && speed.is_finite()) {
+            return Err(anyhow!("a spline path needs a positive speed"));
+        }
+        let mut path = Self {
+            points,
+            world_up,
+            speed,
+            lengths: vec![],
+        };
+        let samples = path.segment_count() * SAMPLES_PER_SEGMENT;
+        let mut length = 0.0;
+        let mut previous = path.position(0.0);
+        path.lengths.push(0.0);
+        for i in 1..=samples {
+            let position = path.position(i as f32 / SAMPLES_PER_SEGMENT as f32);
+            length += glm::distance(&previous, &position);
+            path.lengths.push(length);
+            previous = position;
+        }
+        Ok(path)
+    }
+
+    /// Loads one `x y z` point per line. Empty lines and lines starting with
+    /// `#` are ignored.
+    pub fn load<P>(path: P, world_up: glm::Vec3, speed: f32) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let text = fs::read_to_string(path.as_ref())?;
+        let points = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                let line = line.trim();
+                !line.is_empty() && !line.starts_with('#')
+            })
+            .map(|(i, line)| {
+                let values = parse_floats(line)
+                    .filter(|values| values.len() == 3)
+                    .ok_or_else(|| anyhow!("invalid spline point on line {}", i + 1))?;
+                Ok(glm::vec3(values[0], values[1], values[2]))
+            })
+            .collect::<Result<_>>()?;
+        Self::new(points, world_up, speed)
+    }
+
+    fn segment_count(&self) -> usize {
+        self.points.len() - 1
+    }
+
+    /// Returns the spline position at parameter `u`, where segment `i` spans
+    /// `u` from `i` to `i + 1`. The end points are repeated so the spline
+    /// passes through every point.
+    fn position(&self, u: f32) -> glm::Vec3 {
+        let last = self.segment_count();
+        let segment = (u.floor() as usize).min(last - 1);
+        let t = u - segment as f32;
+        let point = |i: isize| self.points[i.max(0).min(last as isize) as usize];
+        let i = segment as isize;
+        let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+        let (t2, t3) = (t * t, t * t * t);
+        (p1 * 2.0
+            + (p2 - p0) * t
+            + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+            + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+            * 0.5
+    }
+
+    /// Finds the spline parameter at arc length `distance`.
+    fn parameter(&self, distance: f32) -> f32 {
+        let i = match self
+            .lengths
+            .binary_search_by(|length| length.total_cmp(&distance))
+        {
+            Ok(i) => return i as f32 / SAMPLES_PER_SEGMENT as f32,
+            Err(i) => i,
+        };
+        if i == 0 {
+            return 0.0;
+        }
+        if i >= self.lengths.len() {
+            return self.segment_count() as f32;
+        }
+        let (l0, l1) = (self.lengths[i - 1], self.lengths[i]);
+        let s = (distance - l0) / (l1 - l0);
+        (i as f32 - 1.0 + s) / SAMPLES_PER_SEGMENT as f32
+    }
+}
+
+impl CameraPath for SplinePath {
+    fn duration(&self) -> f32 {
+        self.lengths[self.lengths.len() - 1] / self.speed
+    }
+
+    fn sample(&self, time: f32) -> Option<CameraPose> {
+        let end = self.segment_count() as f32;
+        let u = self.parameter(time * self.speed);
+        let position = self.position(u);
+        // Look towards the next point ahead that differs from this one, or
+        // behind at the very end. Repeated points make the path stand still.
+        let step = 1.0 / SAMPLES_PER_SEGMENT as f32;
+        let ahead = (1..)
+            .map(|i| u + i as f32 * step)
+            .take_while(|&u| u <= end)
+            .map(|u| self.position(u) - position);
+        let behind = (1..)
+            .map(|i| u - i as f32 * step)
+            .take_while(|&u| u >= 0.0)
+            .map(|u| position - self.position(u));
+        let front = ahead
+            .chain(behind)
+            .find(|front| glm::length2(front) > f32::EPSILON)
+            .unwrap_or_else(glm::Vec3::zeros);
+        Some(CameraPose {
+            position,
+            orientation: camera::look_rotation(&front, &self.world_up),
+        })
+    }
+}
+
+/// Drives a camera along a path and measures frame times on the way, so that
+/// runs over the same path can be compared.
+pub struct Playback {
+    path: Box<dyn CameraPath>,
+    time: f32,
+    frame_times: Vec<f32>,
+}
+
+impl Playback {
+    pub fn new(path: Box<dyn CameraPath>) -> Self {
+        Self {
+            path,
+            time: 0.0,
+            frame_times: vec![],
+        }
+    }
+
+    /// Moves the camera to the pose after `delta_time` more seconds. Returns
+    /// `false` once the end of the path has been reached.
+    pub fn advance(&mut self, camera: &mut Camera, delta_time: f32) -> bool {
+        if self.time > 0.0 {
+            self.frame_times.push(delta_time);
+        }
+        self.time += delta_time;
+        let pose = match self.path.sample(self.time) {
+            Some(pose) => pose,
+            None => return false,
+        };
+        camera.set_position(pose.position);
+        camera.set_orientation(pose.orientation);
+        self.time < self.path.duration()
+    }
+
+    /// Summarizes the frame times recorded so far.
+    pub fn report(&self) -> String {
+        if self.frame_times.is_empty() {
+            return "no frames played".to_string();
+        }
+        let count = self.frame_times.len();
+        let total: f32 = self.frame_times.iter().sum();
+        let min = self
+            .frame_times
+            .iter()
+            .cloned()
+            .fold(f32::INFINITY, f32::min);
+        let max = self.frame_times.iter().cloned().fold(0.0, f32::max);
+        format!(
+            "{} frames in {:.2} s: average {:.2} ms ({:.1} fps), min {:.2} ms, max {:.2} ms",
+            count,
+            total,
+            total / count as f32 * 1000.0,
+            count as f32 / total,
+            min * 1000.0,
+            max * 1000.0
+        )
+    }
+}
+
+/// Parses whitespace separated numbers, rejecting NaN and infinities.
+fn parse_floats(line: &str) -> Option<Vec<f32>> {
+    line.split_whitespace()
+        .map(|v| v.parse().ok().filter(|v: &f32| v.is_finite()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: &glm::Vec3, b: &glm::Vec3) {
+        assert!(glm::distance(a, b) < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    fn pose(x: f32) -> CameraPose {
+        CameraPose {
+            position: glm::vec3(x, 0.0, 0.0),
+            orientation: glm::quat_identity(),
+        }
+    }
+
+    fn is_finite(pose: &CameraPose) -> bool {
+        pose.position.iter().all(|v| v.is_finite())
+            && pose.orientation.coords.iter().all(|v| v.is_finite())
+    }
+
+    #[test]
+    fn recordings_round_trip_through_text() {
+        let mut recording = CameraRecording::new();
+        recording.push(
+            0.5,
+            CameraPose {
+                position: glm::vec3(1.0, -2.5, 3.25),
+                orientation: glm::quat(0.0, 0.6, 0.0, 0.8),
+            },
+        );
+        recording.push(0.75, pose(4.0));
+        let parsed = CameraRecording::parse(&recording.to_text().unwrap()).unwrap();
+        assert_eq!(parsed.frames, recording.frames);
+    }
+
+    #[test]
+    fn reports_the_line_of_invalid_frames() {
+        let text = "0 0 0 0 0 0 0 1\n\n1 0 0 0 0 0 1\n";
+        let error = CameraRecording::parse(text).unwrap_err();
+        assert_eq!(error.to_string(), "invalid camera frame on line 3");
+        assert!(CameraRecording::parse("0 NaN 0 0 0 0 0 1").is_err());
+        assert!(CameraRecording::parse("0 inf 0 0 0 0 0 1").is_err());
+    }
+
+    #[test]
+    fn recordings_interpolate_between_frames_and_clamp() {
+        let mut recording = CameraRecording::new();
+        recording.push(10.0, pose(0.0));
+        recording.push(12.0, pose(4.0));
+        assert_eq!(recording.duration(), 2.0);
+        assert_near(
+            &recording.sample(-1.0).unwrap().position,
+            &glm::vec3(0.0, 0.0, 0.0),
+        );
+        assert_near(
+            &recording.sample(0.5).unwrap().position,
+            &glm::vec3(1.0, 0.0, 0.0),
+        );
+        assert_near(
+            &recording.sample(5.0).unwrap().position,
+            &glm::vec3(4.0, 0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn empty_recordings_have_no_poses() {
+        let recording = CameraRecording::new();
+        assert_eq!(recording.duration(), 0.0);
+        assert!(recording.sample(0.0).is_none());
+    }
+
+    #[test]
+    fn splines_pass_through_their_points() {
+        let points = vec![
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(1.0, 2.0, 0.0),
+            glm::vec3(3.0, 2.0, -1.0),
+            glm::vec3(4.0, 0.0, 0.0),
+        ];
+        let path = SplinePath::new(points.clone(), glm::vec3(0.0, 1.0, 0.0), 1.0).unwrap();
+        for (i, point) in points.iter().enumerate() {
+            assert_near(&path.position(i as f32), point);
+        }
+    }
+
+    #[test]
+    fn splines_are_followed_at_constant_speed() {
+        let points = vec![
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(5.0, 0.0, 0.0),
+            glm::vec3(10.0, 0.0, 0.0),
+        ];
+        let path = SplinePath::new(points, glm::vec3(0.0, 1.0, 0.0), 2.0).unwrap();
+        assert!((path.duration() - 5.0).abs() < 1e-3);
+        for &(time, x) in [
+            (0.0, 0.0),
+            (1.25, 2.5),
+            (2.5, 5.0),
+            (5.0, 10.0),
+            (9.0, 10.0),
+        ]
+        .iter()
+        {
+            assert_near(
+                &path.sample(time).unwrap().position,
+                &glm::vec3(x, 0.0, 0.0),
+            );
+        }
+        // Travelling along +X looks along +X.
+        let orientation = path.sample(1.0).unwrap().orientation;
+        let front = glm::quat_rotate_vec3(&orientation, &glm::vec3(0.0, 0.0, -1.0));
+        assert_near(&front, &glm::vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn repeated_and_vertical_points_give_finite_poses() {
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        let repeated = vec![
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, 0.0),
+            glm::vec3(0.0, 0.0, 4.0),
+            glm::vec3(0.0, 0.0, 4.0),
+        ];
+        let vertical = vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 10.0, 0.0)];
+        let still = vec![glm::vec3(1.0, 1.0, 1.0), glm::vec3(1.0, 1.0, 1.0)];
+        for points in [repeated, vertical, still].iter() {
+            let path = SplinePath::new(points.clone(), up, 1.0).unwrap();
+            for i in 0..=20 {
+                let time = path.duration() * i as f32 / 20.0;
+                assert!(
+                    is_finite(&path.sample(time).unwrap()),
+                    "{:?} at {}",
+                    points,
+                    time
+                );
+            }
+            assert!(is_finite(&path.sample(f32::NAN).unwrap()));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_splines() {
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        assert!(SplinePath::new(vec![glm::vec3(0.0, 0.0, 0.0)], up, 1.0).is_err());
+        let nan = vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(f32::NAN, 0.0, 0.0)];
+        assert!(SplinePath::new(nan, up, 1.0).is_err());
+        let points = vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 0.0, 0.0)];
+        assert!(SplinePath::new(points, up, 0.0).is_err());
+    }
+}
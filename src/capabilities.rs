@@ -0,0 +1,57 @@
+use std::ffi::CStr;
+
+use gl::types::*;
+
+pub fn gl_version() -> (i32, i32) {
+    let mut major = 0;
+    let mut minor = 0;
+    unsafe {
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+    }
+    (major, minor)
+}
+
+pub fn has_extension(name: &str) -> bool {
+    let mut count = 0;
+    unsafe {
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut count);
+    }
+    (0..count as GLuint).any(|i| {
+        let extension = unsafe { gl::GetStringi(gl::EXTENSIONS, i) };
+        !extension.is_null()
+            && unsafe { CStr::from_ptr(extension as *const GLchar) }.to_bytes() == name.as_bytes()
+    })
+}
+
+pub fn compute_shaders_supported() -> bool {
+    gl::DispatchCompute::is_loaded() && gl_version() >= (4, 3)
+}
+
+/// Whether `glClipControl` can switch to a [0, 1] depth range, which reversed-Z
+/// projections need to keep their precision.
+pub fn clip_control_supported() -> bool {
+    gl::ClipControl::is_loaded() && (gl_version() >= (4, 5) || has_extension("GL_ARB_clip_control"))
+}
+
+pub fn anisotropic_filtering_supported() -> bool {
+    gl_version() >= (4, 6)
+        || has_extension("GL_ARB_texture_filter_anisotropic")
+        || has_extension("GL_EXT_texture_filter_anisotropic")
+}
+
+/// Whether the BC1 to BC3 formats, known as S3TC or DXT, can be sampled.
+pub fn s3tc_supported() -> bool {
+    has_extension("GL_EXT_texture_compression_s3tc")
+}
+
+pub fn s3tc_srgb_supported() -> bool {
+    s3tc_supported()
+        && (has_extension("GL_EXT_texture_sRGB")
+            || has_extension("GL_EXT_texture_compression_s3tc_srgb"))
+}
+
+/// Whether the BC6H and BC7 formats, known as BPTC, can be sampled.
+pub fn bptc_supported() -> bool {
+    gl_version() >= (4, 2) || has_extension("GL_ARB_texture_compression_bptc")
+}
@@ -0,0 +1,541 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use gl::types::*;
+
+use crate::capabilities;
+
+// S3TC formats come from GL_EXT_texture_compression_s3tc and
+// GL_EXT_texture_sRGB, which are not part of core OpenGL.
+const COMPRESSED_RGB_S3TC_DXT1: GLenum = 0x83F0;
+const COMPRESSED_RGBA_S3TC_DXT1: GLenum = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT3: GLenum = 0x83F2;
+const COMPRESSED_RGBA_S3TC_DXT5: GLenum = 0x83F3;
+const COMPRESSED_SRGB_S3TC_DXT1: GLenum = 0x8C4C;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: GLenum = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT3: GLenum = 0x8C4E;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: GLenum = 0x8C4F;
+
+/// The formats the driver can sample, see `detect_supported_formats`.
+static SUPPORTED_FORMATS: OnceLock<Vec<CompressedFormat>> = OnceLock::new();
+
+const DDS_MAGIC: &[u8] = b"DDS ";
+const KTX_IDENTIFIER: &[u8] = b"\xABKTX 11\xBB\r\n\x1A\n";
+const KTX2_IDENTIFIER: &[u8] = b"\xABKTX 20\xBB\r\n\x1A\n";
+
+/// Block compressed formats, each storing 4x4 texel blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressedFormat {
+    Bc1,
+    Bc1Srgb,
+    Bc1Alpha,
+    Bc1AlphaSrgb,
+    Bc2,
+    Bc2Srgb,
+    Bc3,
+    Bc3Srgb,
+    Bc4,
+    Bc4Signed,
+    Bc5,
+    Bc5Signed,
+    Bc6hUnsigned,
+    Bc6hSigned,
+    Bc7,
+    Bc7Srgb,
+}
+
+use CompressedFormat::*;
+
+/// Each format with its OpenGL internal format, DXGI format as found in DDS
+/// files (0 if there is none) and Vulkan format as found in KTX2 files.
+const FORMATS: [(CompressedFormat, GLenum, u32, u32); 16] = [
+    (Bc1, COMPRESSED_RGB_S3TC_DXT1, 0, 131),
+    (Bc1Srgb, COMPRESSED_SRGB_S3TC_DXT1, 0, 132),
+    (Bc1Alpha, COMPRESSED_RGBA_S3TC_DXT1, 71, 133),
+    (Bc1AlphaSrgb, COMPRESSED_SRGB_ALPHA_S3TC_DXT1, 72, 134),
+    (Bc2, COMPRESSED_RGBA_S3TC_DXT3, 74, 135),
+    (Bc2Srgb, COMPRESSED_SRGB_ALPHA_S3TC_DXT3, 75, 136),
+    (Bc3, COMPRESSED_RGBA_S3TC_DXT5, 77, 137),
+    (Bc3Srgb, COMPRESSED_SRGB_ALPHA_S3TC_DXT5, 78, 138),
+    (Bc4, gl::COMPRESSED_RED_RGTC1, 80, 139),
+    (Bc4Signed, gl::COMPRESSED_SIGNED_RED_RGTC1, 81, 140),
+    (Bc5, gl::COMPRESSED_RG_RGTC2, 83, 141),
+    (Bc5Signed, gl::COMPRESSED_SIGNED_RG_RGTC2, 84, 142),
+    (
+        Bc6hUnsigned,
+        gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT,
+        95,
+        143,
+    ),
+    (Bc6hSigned, gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT, 96, 144),
+    (Bc7, gl::COMPRESSED_RGBA_BPTC_UNORM, 98, 145),
+    (Bc7Srgb, gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM, 99, 146),
+];
+
+impl CompressedFormat {
+    fn from_gl(format: GLenum) -> Option<Self> {
+        FORMATS.iter().find(|f| f.1 == format).map(|f| f.0)
+    }
+
+    fn from_dxgi(format: u32) -> Option<Self> {
+        FORMATS
+            .iter()
+            .find(|f| f.2 != 0 && f.2 == format)
+            .map(|f| f.0)
+    }
+
+    fn from_vulkan(format: u32) -> Option<Self> {
+        FORMATS.iter().find(|f| f.3 == format).map(|f| f.0)
+    }
+
+    pub fn gl_format(self) -> GLenum {
+        FORMATS.iter().find(|f| f.0 == self).unwrap().1
+    }
+
+    /// Bytes per 4x4 block.
+    pub fn block_size(self) -> usize {
+        match self {
+            Bc1 | Bc1Srgb | Bc1Alpha | Bc1AlphaSrgb | Bc4 | Bc4Signed => 8,
+            _ => 16,
+        }
+    }
+
+    /// Returns the sRGB variant of a color format, which shares its block
+    /// layout, or `None` for formats without one.
+    pub fn srgb(self) -> Option<Self> {
+        match self {
+            Bc1 | Bc1Srgb => Some(Bc1Srgb),
+            Bc1Alpha | Bc1AlphaSrgb => Some(Bc1AlphaSrgb),
+            Bc2 | Bc2Srgb => Some(Bc2Srgb),
+            Bc3 | Bc3Srgb => Some(Bc3Srgb),
+            Bc7 | Bc7Srgb => Some(Bc7Srgb),
+            _ => None,
+        }
+    }
+
+    /// Whether the driver can sample the format directly. Answers from what
+    /// `detect_supported_formats` found, so it can be asked on any thread.
+    /// Panics if that has not been called yet.
+    pub fn supported(self) -> bool {
+        SUPPORTED_FORMATS
+            .get()
+            .expect("detect_supported_formats must be called before decoding compressed textures")
+            .contains(&self)
+    }
+
+    fn query_support(self) -> bool {
+        match self {
+            Bc1 | Bc1Alpha | Bc2 | Bc3 => capabilities::s3tc_supported(),
+            Bc1Srgb | Bc1AlphaSrgb | Bc2Srgb | Bc3Srgb => capabilities::s3tc_srgb_supported(),
+            // RGTC is core since OpenGL 3.0.
+            Bc4 | Bc4Signed | Bc5 | Bc5Signed => true,
+            Bc6hUnsigned | Bc6hSigned | Bc7 | Bc7Srgb => capabilities::bptc_supported(),
+        }
+    }
+
+    /// Returns the internal format, pixel format, pixel type and bytes per
+    /// texel of the uncompressed data `bcn::decompress` produces.
+    pub fn fallback_format(self) -> (GLenum, GLenum, GLenum, usize) {
+        match self {
+            Bc1 | Bc1Alpha | Bc2 | Bc3 | Bc7 => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE, 4),
+            Bc1Srgb | Bc1AlphaSrgb | Bc2Srgb | Bc3Srgb | Bc7Srgb => {
+                (gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE, 4)
+            }
+            Bc4 => (gl::R8, gl::RED, gl::UNSIGNED_BYTE, 1),
+            Bc4Signed => (gl::R8_SNORM, gl::RED, gl::BYTE, 1),
+            Bc5 => (gl::RG8, gl::RG, gl::UNSIGNED_BYTE, 2),
+            Bc5Signed => (gl::RG8_SNORM, gl::RG, gl::BYTE, 2),
+            Bc6hUnsigned | Bc6hSigned => (gl::RGB16F, gl::RGB, gl::FLOAT, 12),
+        }
+    }
+
+    /// Bytes taken by an image of the given size. Saturates rather than
+    /// overflowing for the sizes of corrupt headers, which no file can hold.
+    fn image_size(self, width: u32, height: u32) -> usize {
+        let blocks_x = width.div_ceil(4) as usize;
+        let blocks_y = height.div_ceil(4) as usize;
+        blocks_x
+            .saturating_mul(blocks_y)
+            .saturating_mul(self.block_size())
+    }
+}
+
+/// Finds out which formats the driver can sample. Must be called on the
+/// thread owning the GL context before images are decoded, since images in
+/// unsupported formats are decompressed as they are decoded.
+pub fn detect_supported_formats() {
+    SUPPORTED_FORMATS.get_or_init(|| {
+        FORMATS
+            .iter()
+            .map(|&(format, ..)| format)
+            .filter(|format| format.query_support())
+            .collect()
+    });
+}
+
+/// A block compressed 2D image with its mipmap levels, as stored in a DDS, KTX
+/// or KTX2 file.
+#[derive(Debug)]
+pub struct CompressedImage {
+    pub format: CompressedFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Level 0 first.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    /// Loads a DDS, KTX or KTX2 file, telling them apart by their contents.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let image = if data.starts_with(DDS_MAGIC) {
+            parse_dds(&data)
+        } else if data.starts_with(KTX_IDENTIFIER) {
+            parse_ktx(&data)
+        } else if data.starts_with(KTX2_IDENTIFIER) {
+            parse_ktx2(&data)
+        } else {
+            Err(anyhow!("not a DDS, KTX or KTX2 file"))
+        };
+        image.map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        let size = |size: u32| size.checked_shr(level as u32).unwrap_or(0).max(1);
+        (size(self.width), size(self.height))
+    }
+}
+
+fn parse_dds(data: &[u8]) -> Result<CompressedImage> {
+    const MIPMAP_COUNT: u32 = 0x20000;
+    const FOURCC: u32 = 0x4;
+    const CUBEMAP: u32 = 0x200;
+    const VOLUME: u32 = 0x200000;
+    const DIMENSION_TEXTURE_2D: u32 = 3;
+    const MISC_TEXTURE_CUBE: u32 = 0x4;
+
+    let flags = read_u32(data, 8)?;
+    let height = read_u32(data, 12)?;
+    let width = read_u32(data, 16)?;
+    let level_count = if flags & MIPMAP_COUNT != 0 {
+        read_u32(data, 28)?.max(1)
+    } else {
+        1
+    };
+    if read_u32(data, 80)? & FOURCC == 0 {
+        return Err(anyhow!("uncompressed DDS files are not supported"));
+    }
+    if read_u32(data, 112)? & (CUBEMAP | VOLUME) != 0 {
+        return Err(anyhow!("only 2D DDS textures are supported"));
+    }
+    let fourcc = read_bytes(data, 84, 4)?;
+    let (format, mut offset) = match fourcc {
+        b"DXT1" => (Bc1Alpha, 128),
+        b"DXT3" => (Bc2, 128),
+        b"DXT5" => (Bc3, 128),
+        b"ATI1" | b"BC4U" => (Bc4, 128),
+        b"BC4S" => (Bc4Signed, 128),
+        b"ATI2" | b"BC5U" => (Bc5, 128),
+        b"BC5S" => (Bc5Signed, 128),
+        b"DX10" => {
+            let dxgi_format = read_u32(data, 128)?;
+            if read_u32(data, 132)? != DIMENSION_TEXTURE_2D
+                || read_u32(data, 136)? & MISC_TEXTURE_CUBE != 0
+                || read_u32(data, 140)? > 1
+            {
+                return Err(anyhow!("only 2D DDS textures are supported"));
+            }
+            let format = CompressedFormat::from_dxgi(dxgi_format)
+                .ok_or_else(|| anyhow!("unsupported DXGI format {}", dxgi_format))?;
+            (format, 148)
+        }
+        _ => {
+            return Err(anyhow!(
+                "unsupported DDS format {}",
+                String::from_utf8_lossy(fourcc)
+            ))
+        }
+    };
+
+    let mut image = CompressedImage {
+        format,
+        width,
+        height,
+        levels: vec![],
+    };
+    for level in 0..level_count as usize {
+        let (width, height) = image.level_size(level);
+        let size = format.image_size(width, height);
+        image.levels.push(read_bytes(data, offset, size)?.to_vec());
+        offset += size;
+    }
+    Ok(image)
+}
+
+fn parse_ktx(data: &[u8]) -> Result<CompressedImage> {
+    const ENDIANNESS: u32 = 0x04030201;
+
+    if read_u32(data, 12)? != ENDIANNESS {
+        return Err(anyhow!("big endian KTX files are not supported"));
+    }
+    let gl_type = read_u32(data, 16)?;
+    let internal_format = read_u32(data, 28)?;
+    let width = read_u32(data, 36)?;
+    let height = read_u32(data, 40)?;
+    let depth = read_u32(data, 44)?;
+    let array_elements = read_u32(data, 48)?;
+    let faces = read_u32(data, 52)?;
+    let level_count = read_u32(data, 56)?.max(1);
+    let key_value_bytes = read_u32(data, 60)? as usize;
+    if gl_type != 0 {
+        return Err(anyhow!("uncompressed KTX files are not supported"));
+    }
+    if depth != 0 || array_elements != 0 || faces != 1 {
+        return Err(anyhow!("only 2D KTX textures are supported"));
+    }
+    let format = CompressedFormat::from_gl(internal_format)
+        .ok_or_else(|| anyhow!("unsupported KTX format {:#x}", internal_format))?;
+
+    let mut levels = vec![];
+    let mut offset = 64 + key_value_bytes;
+    for _ in 0..level_count {
+        let size = read_u32(data, offset)? as usize;
+        levels.push(read_bytes(data, offset + 4, size)?.to_vec());
+        // Each level is padded to a multiple of 4 bytes.
+        offset += 4 + size.div_ceil(4) * 4;
+    }
+    let image = CompressedImage {
+        format,
+        width,
+        height,
+        levels,
+    };
+    check_levels(&image)?;
+    Ok(image)
+}
+
+fn parse_ktx2(data: &[u8]) -> Result<CompressedImage> {
+    let vulkan_format = read_u32(data, 12)?;
+    let width = read_u32(data, 20)?;
+    let height = read_u32(data, 24)?;
+    let depth = read_u32(data, 28)?;
+    let layers = read_u32(data, 32)?;
+    let faces = read_u32(data, 36)?;
+    let level_count = read_u32(data, 40)?.max(1);
+    let supercompression = read_u32(data, 44)?;
+    if supercompression != 0 {
+        return Err(anyhow!("supercompressed KTX2 files are not supported"));
+    }
+    if depth != 0 || layers != 0 || faces != 1 {
+        return Err(anyhow!("only 2D KTX2 textures are supported"));
+    }
+    let format = CompressedFormat::from_vulkan(vulkan_format)
+        .ok_or_else(|| anyhow!("unsupported KTX2 format {}", vulkan_format))?;
+
+    let levels = (0..level_count as usize)
+        .map(|level| {
+            let index = 80 + level * 24;
+            let offset = usize::try_from(read_u64(data, index)?)?;
+            let size = usize::try_from(read_u64(data, index + 8)?)?;
+            Ok(read_bytes(data, offset, size)?.to_vec())
+        })
+        .collect::<Result<_>>()?;
+    let image = CompressedImage {
+        format,
+        width,
+        height,
+        levels,
+    };
+    check_levels(&image)?;
+    Ok(image)
+}
+
+/// Checks that every level holds as many blocks as its size needs.
+fn check_levels(image: &CompressedImage) -> Result<()> {
+    for (level, data) in image.levels.iter().enumerate() {
+        let (width, height) = image.level_size(level);
+        if data.len() != image.format.image_size(width, height) {
+            return Err(anyhow!("mipmap level {} has the wrong size", level));
+        }
+    }
+    Ok(())
+}
+
+fn read_bytes(data: &[u8], offset: usize, size: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| anyhow!("file is truncated"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = read_bytes(data, offset, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let low = read_u32(data, offset)? as u64;
+    let high = read_u32(data, offset + 4)? as u64;
+    Ok(high << 32 | low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(data: &mut Vec<u8>, offset: usize, value: u32) {
+        if data.len() < offset + 4 {
+            data.resize(offset + 4, 0);
+        }
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(data: &mut Vec<u8>, offset: usize, value: u64) {
+        put_u32(data, offset, value as u32);
+        put_u32(data, offset + 4, (value >> 32) as u32);
+    }
+
+    /// A DDS header for a `width` by `height` texture with `levels` mipmap
+    /// levels in the legacy FourCC format `fourcc`.
+    fn dds_header(fourcc: &[u8; 4], width: u32, height: u32, levels: u32) -> Vec<u8> {
+        let mut data = DDS_MAGIC.to_vec();
+        put_u32(&mut data, 4, 124);
+        put_u32(&mut data, 8, 0x20000);
+        put_u32(&mut data, 12, height);
+        put_u32(&mut data, 16, width);
+        put_u32(&mut data, 28, levels);
+        put_u32(&mut data, 80, 0x4);
+        data.resize(128, 0);
+        data[84..88].copy_from_slice(fourcc);
+        data
+    }
+
+    fn ktx_header(internal_format: GLenum, width: u32, height: u32, levels: u32) -> Vec<u8> {
+        let mut data = KTX_IDENTIFIER.to_vec();
+        put_u32(&mut data, 12, 0x04030201);
+        put_u32(&mut data, 28, internal_format);
+        put_u32(&mut data, 36, width);
+        put_u32(&mut data, 40, height);
+        put_u32(&mut data, 52, 1);
+        put_u32(&mut data, 56, levels);
+        put_u32(&mut data, 60, 0);
+        data
+    }
+
+    /// A KTX2 header for a single level, given as its offset and size in
+    /// bytes. The level index ends at byte 104.
+    fn ktx2_file(vulkan_format: u32, width: u32, height: u32, level: (u64, u64)) -> Vec<u8> {
+        let mut data = KTX2_IDENTIFIER.to_vec();
+        put_u32(&mut data, 12, vulkan_format);
+        put_u32(&mut data, 20, width);
+        put_u32(&mut data, 24, height);
+        put_u32(&mut data, 36, 1);
+        put_u32(&mut data, 40, 1);
+        put_u64(&mut data, 80, level.0);
+        put_u64(&mut data, 88, level.1);
+        put_u64(&mut data, 96, level.1);
+        data
+    }
+
+    #[test]
+    fn parses_dds_mipmaps() {
+        let mut data = dds_header(b"DXT5", 8, 8, 4);
+        // 2x2 blocks, then one block for each of the 4x4, 2x2 and 1x1 levels.
+        data.extend((0..16 * 7).map(|i| i as u8));
+        let image = parse_dds(&data).unwrap();
+        assert_eq!(image.format, Bc3);
+        assert_eq!((image.width, image.height), (8, 8));
+        let sizes: Vec<_> = image.levels.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [64, 16, 16, 16]);
+        assert_eq!(image.levels[1][0], 64);
+        assert_eq!(image.level_size(3), (1, 1));
+    }
+
+    #[test]
+    fn parses_dx10_dds() {
+        let mut data = dds_header(b"DX10", 4, 4, 1);
+        put_u32(&mut data, 128, 98);
+        put_u32(&mut data, 132, 3);
+        put_u32(&mut data, 140, 1);
+        put_u32(&mut data, 144, 0);
+        data.extend([0; 16].iter());
+        let image = parse_dds(&data).unwrap();
+        assert_eq!(image.format, Bc7);
+        assert_eq!(image.levels, vec![vec![0; 16]]);
+    }
+
+    #[test]
+    fn rejects_truncated_dds() {
+        let mut data = dds_header(b"DXT1", 8, 4, 1);
+        data.extend([0; 15].iter());
+        let error = parse_dds(&data).unwrap_err();
+        assert_eq!(error.to_string(), "file is truncated");
+        assert!(parse_dds(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_dds() {
+        let data = dds_header(b"RGBG", 4, 4, 1);
+        assert!(parse_dds(&data).is_err());
+        let mut data = dds_header(b"DXT1", 4, 4, 1);
+        put_u32(&mut data, 112, 0x200);
+        assert!(parse_dds(&data).is_err());
+    }
+
+    #[test]
+    fn survives_huge_dds_sizes() {
+        let data = dds_header(b"DXT1", u32::MAX, u32::MAX, 1);
+        assert!(parse_dds(&data).is_err());
+    }
+
+    #[test]
+    fn parses_padded_ktx_levels() {
+        let mut data = ktx_header(gl::COMPRESSED_RED_RGTC1, 4, 4, 1);
+        put_u32(&mut data, 64, 8);
+        data.extend([7; 8].iter());
+        let image = parse_ktx(&data).unwrap();
+        assert_eq!(image.format, Bc4);
+        assert_eq!(image.levels, vec![vec![7; 8]]);
+    }
+
+    #[test]
+    fn rejects_ktx_levels_of_the_wrong_size() {
+        let mut data = ktx_header(gl::COMPRESSED_RED_RGTC1, 8, 8, 1);
+        put_u32(&mut data, 64, 8);
+        data.extend([0; 8].iter());
+        assert!(parse_ktx(&data).is_err());
+    }
+
+    #[test]
+    fn parses_ktx2() {
+        let mut data = ktx2_file(145, 4, 4, (104, 16));
+        data.extend([1; 16].iter());
+        let image = parse_ktx2(&data).unwrap();
+        assert_eq!(image.format, Bc7);
+        assert_eq!(image.levels, vec![vec![1; 16]]);
+    }
+
+    #[test]
+    fn rejects_short_ktx2_levels() {
+        let mut data = ktx2_file(145, 8, 4, (104, 16));
+        data.extend([1; 16].iter());
+        assert!(parse_ktx2(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_ktx2_levels_out_of_bounds() {
+        let data = ktx2_file(145, 4, 4, (u64::MAX, 16));
+        assert!(parse_ktx2(&data).is_err());
+        let data = ktx2_file(145, 4, 4, (104, u64::MAX));
+        assert!(parse_ktx2(&data).is_err());
+        let data = ktx2_file(145, 4, 4, (u64::MAX - 4, 8));
+        assert!(parse_ktx2(&data).is_err());
+    }
+}
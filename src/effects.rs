@@ -0,0 +1,176 @@
+use std::rc::Rc;
+
+use nalgebra_glm as glm;
+
+use crate::model::Model;
+use crate::sampler::Sampler;
+use crate::shader_error::ShaderError;
+use crate::shader_program::ShaderProgram;
+use crate::texture::{Filter, TextureOptions};
+use crate::texture_units::TextureUnits;
+
+const NORMALS_VERTEX_SHADER: &str = include_str!("shaders/normals.vert");
+const NORMALS_GEOMETRY_SHADER: &str = include_str!("shaders/normals.geom");
+const NORMALS_FRAGMENT_SHADER: &str = include_str!("shaders/normals.frag");
+
+const EXPLODE_VERTEX_SHADER: &str = include_str!("shaders/explode.vert");
+const EXPLODE_GEOMETRY_SHADER: &str = include_str!("shaders/explode.geom");
+const EXPLODE_FRAGMENT_SHADER: &str = include_str!("shaders/main.frag");
+
+const OUTLINE_VERTEX_SHADER: &str = include_str!("shaders/outline.vert");
+const OUTLINE_FRAGMENT_SHADER: &str = include_str!("shaders/outline.frag");
+
+/// Draws each vertex normal of a model as a line segment.
+pub struct NormalVisualizer {
+    shader: ShaderProgram,
+    pub magnitude: f32,
+    pub color: glm::Vec3,
+}
+
+impl NormalVisualizer {
+    pub fn new() -> Result<Self, ShaderError> {
+        let shader = ShaderProgram::new(
+            NORMALS_VERTEX_SHADER,
+            NORMALS_FRAGMENT_SHADER,
+            Some(NORMALS_GEOMETRY_SHADER),
+        )?;
+        Ok(Self {
+            shader,
+            magnitude: 0.1,
+            color: glm::vec3(1.0, 1.0, 0.0),
+        })
+    }
+
+    pub unsafe fn draw(
+        &self,
+        model: &Model,
+        model_matrix: glm::Mat4,
+        view: glm::Mat4,
+        projection: glm::Mat4,
+        texture_units: &mut TextureUnits,
+    ) -> anyhow::Result<()> {
+        self.shader.use_program();
+        self.shader.set_uniform_mat4f("model", model_matrix);
+        self.shader.set_uniform_mat4f("view", view);
+        self.shader.set_uniform_mat4f("projection", projection);
+        self.shader.set_uniform_float("magnitude", self.magnitude);
+        self.shader.set_uniform_vec3f("color", self.color);
+        model.draw(&self.shader, texture_units)
+    }
+}
+
+/// Pushes every triangle of a model outwards along its face normal, oscillating
+/// with time. The textures are sampled without filtering, which gives the
+/// pieces a blocky look.
+pub struct Explode {
+    shader: ShaderProgram,
+    sampler: Rc<Sampler>,
+    pub magnitude: f32,
+}
+
+impl Explode {
+    pub fn new() -> Result<Self, ShaderError> {
+        let shader = ShaderProgram::new(
+            EXPLODE_VERTEX_SHADER,
+            EXPLODE_FRAGMENT_SHADER,
+            Some(EXPLODE_GEOMETRY_SHADER),
+        )?;
+        let sampler_options = TextureOptions::default()
+            .filter(Filter::Nearest, Filter::Nearest)
+            .mipmaps(None);
+        let sampler = Rc::new(unsafe { Sampler::new(&sampler_options) });
+        Ok(Self {
+            shader,
+            sampler,
+            magnitude: 2.0,
+        })
+    }
+
+    pub unsafe fn draw(
+        &self,
+        model: &Model,
+        model_matrix: glm::Mat4,
+        view: glm::Mat4,
+        projection: glm::Mat4,
+        time: f32,
+        texture_units: &mut TextureUnits,
+    ) -> anyhow::Result<()> {
+        self.shader.use_program();
+        self.shader.set_uniform_mat4f("model", model_matrix);
+        self.shader.set_uniform_mat4f("view", view);
+        self.shader.set_uniform_mat4f("projection", projection);
+        self.shader.set_uniform_float("time", time);
+        self.shader.set_uniform_float("magnitude", self.magnitude);
+        model.draw_with_sampler(&self.shader, texture_units, &self.sampler)
+    }
+}
+
+/// Outlines a model with the stencil buffer: the model's silhouette is marked
+/// in the stencil buffer, then the model grown along its normals is drawn
+/// everywhere outside that mark. The outline shows through other objects.
+pub struct Outline {
+    shader: ShaderProgram,
+    pub color: glm::Vec3,
+    /// Width in pixels.
+    pub thickness: f32,
+}
+
+impl Outline {
+    pub fn new() -> Result<Self, ShaderError> {
+        let shader = ShaderProgram::new(OUTLINE_VERTEX_SHADER, OUTLINE_FRAGMENT_SHADER, None)?;
+        Ok(Self {
+            shader,
+            color: glm::vec3(1.0, 0.6, 0.0),
+            thickness: 3.0,
+        })
+    }
+
+    /// Needs a stencil buffer, which is cleared and left cleared of the mark.
+    pub unsafe fn draw(
+        &self,
+        model: &Model,
+        model_matrix: glm::Mat4,
+        view: glm::Mat4,
+        projection: glm::Mat4,
+    ) {
+        let mut viewport = [0; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        self.shader.use_program();
+        self.shader.set_uniform_mat4f("model", model_matrix);
+        self.shader.set_uniform_mat4f("view", view);
+        self.shader.set_uniform_mat4f("projection", projection);
+        self.shader.set_uniform_vec3f("color", self.color);
+        self.shader.set_uniform_vec2f(
+            "viewportSize",
+            glm::vec2(viewport[2] as f32, viewport[3] as f32),
+        );
+
+        gl::Enable(gl::STENCIL_TEST);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::StencilMask(0xFF);
+        gl::Clear(gl::STENCIL_BUFFER_BIT);
+
+        // Mark the silhouette without drawing anything.
+        gl::StencilFunc(gl::ALWAYS, 1, 0xFF);
+        gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
+        gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+        self.shader.set_uniform_float("thickness", 0.0);
+        for mesh in model.meshes.iter() {
+            mesh.draw_elements(1);
+        }
+
+        // Draw the grown model only around the mark.
+        gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+        gl::StencilFunc(gl::NOTEQUAL, 1, 0xFF);
+        gl::StencilMask(0x00);
+        self.shader.set_uniform_float("thickness", self.thickness);
+        for mesh in model.meshes.iter() {
+            mesh.draw_elements(1);
+        }
+
+        gl::StencilMask(0xFF);
+        gl::Clear(gl::STENCIL_BUFFER_BIT);
+        gl::Disable(gl::STENCIL_TEST);
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
@@ -0,0 +1,160 @@
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs, GilrsBuilder};
+
+use crate::input::{GamepadAxis, GamepadButton, Input};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GamepadSettings {
+    /// Stick deflection, from 0 to 1, below which a stick reads as centred.
+    pub stick_dead_zone: f32,
+    /// Trigger travel, from 0 to 1, below which a trigger reads as released.
+    pub trigger_dead_zone: f32,
+    /// Exponent applied to deflection past the dead zone. Values above 1 give
+    /// finer control near the centre while keeping full speed at the edge.
+    pub response_exponent: f32,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            stick_dead_zone: 0.15,
+            trigger_dead_zone: 0.05,
+            response_exponent: 2.0,
+        }
+    }
+}
+
+/// Reads connected gamepads and feeds them into `Input`. Sticks and triggers
+/// are taken from the gamepad used last, buttons from all of them.
+pub struct Gamepads {
+    /// `None` when gamepads are unsupported on this system.
+    gilrs: Option<Gilrs>,
+    active: Option<GamepadId>,
+    settings: GamepadSettings,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        // Dead zones are applied here, so gilrs' own filters are turned off.
+        let gilrs = match GilrsBuilder::new().with_default_filters(false).build() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                eprintln!("Gamepads are unavailable: {}", e);
+                None
+            }
+        };
+        Self {
+            gilrs,
+            active: None,
+            settings: GamepadSettings::default(),
+        }
+    }
+
+    /// Handles the events since the last call and updates the gamepad axes.
+    pub fn poll(&mut self, input: &mut Input) {
+        let gilrs = match &mut self.gilrs {
+            Some(gilrs) => gilrs,
+            None => return,
+        };
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) | EventType::ButtonReleased(button, _) => {
+                    self.active = Some(event.id);
+                    let pressed = matches!(event.event, EventType::ButtonPressed(..));
+                    if let Some(button) = gamepad_button(button) {
+                        input.set_gamepad_button(button, pressed);
+                    }
+                }
+                EventType::ButtonChanged(..) | EventType::AxisChanged(..) => {
+                    self.active = Some(event.id)
+                }
+                EventType::Disconnected => {
+                    if self.active == Some(event.id) {
+                        self.active = None;
+                    }
+                    input.reset_gamepad();
+                }
+                _ => {}
+            }
+        }
+
+        let gamepad = match self.active.and_then(|id| gilrs.connected_gamepad(id)) {
+            Some(gamepad) => gamepad,
+            None => return,
+        };
+        let settings = self.settings;
+        let (left_x, left_y) = stick(
+            gamepad.value(Axis::LeftStickX),
+            gamepad.value(Axis::LeftStickY),
+            &settings,
+        );
+        let (right_x, right_y) = stick(
+            gamepad.value(Axis::RightStickX),
+            gamepad.value(Axis::RightStickY),
+            &settings,
+        );
+        let trigger_value = |button| {
+            let value = gamepad.button_data(button).map_or(0.0, |data| data.value());
+            response(
+                value,
+                settings.trigger_dead_zone,
+                settings.response_exponent,
+            )
+        };
+        input.set_gamepad_axis(GamepadAxis::LeftStickX, left_x);
+        input.set_gamepad_axis(GamepadAxis::LeftStickY, left_y);
+        input.set_gamepad_axis(GamepadAxis::RightStickX, right_x);
+        input.set_gamepad_axis(GamepadAxis::RightStickY, right_y);
+        input.set_gamepad_axis(
+            GamepadAxis::LeftTrigger,
+            trigger_value(Button::LeftTrigger2),
+        );
+        input.set_gamepad_axis(
+            GamepadAxis::RightTrigger,
+            trigger_value(Button::RightTrigger2),
+        );
+    }
+}
+
+/// Applies a radial dead zone and the response curve to a stick, keeping its
+/// direction so diagonals are not snapped to the axes.
+fn stick(x: f32, y: f32, settings: &GamepadSettings) -> (f32, f32) {
+    let magnitude = x.hypot(y);
+    if magnitude == 0.0 {
+        return (0.0, 0.0);
+    }
+    let scale = response(
+        magnitude,
+        settings.stick_dead_zone,
+        settings.response_exponent,
+    ) / magnitude;
+    (x * scale, y * scale)
+}
+
+/// Maps `value` from the range `dead_zone..=1` to `0..=1` along a power curve.
+fn response(value: f32, dead_zone: f32, exponent: f32) -> f32 {
+    let value = ((value - dead_zone) / (1.0 - dead_zone)).clamp(0.0, 1.0);
+    value.powf(exponent)
+}
+
+fn gamepad_button(button: Button) -> Option<GamepadButton> {
+    let button = match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::LeftTrigger2 => GamepadButton::LeftTrigger,
+        Button::RightTrigger2 => GamepadButton::RightTrigger,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftStick,
+        Button::RightThumb => GamepadButton::RightStick,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    };
+    Some(button)
+}
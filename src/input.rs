@@ -0,0 +1,795 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+
+const DEFAULT_BINDINGS: &str = include_str!("input.cfg");
+
+/// Pixels of touchpad scrolling that count as one line of a mouse wheel.
+const PIXELS_PER_SCROLL_LINE: f64 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    RollLeft,
+    RollRight,
+    Sprint,
+    Slow,
+    /// Makes scrolling zoom instead of changing the flying speed.
+    ZoomModifier,
+    ToggleMouseLook,
+    /// Looks around with the mouse while held, even with mouse look off.
+    HoldMouseLook,
+    /// Grabs the cursor again after the window lost focus.
+    GrabCursor,
+    /// Selects the object under the cursor, or at the centre during mouse
+    /// look.
+    Select,
+    ToggleNormals,
+    ToggleExplode,
+    ToggleAnimation,
+    ToggleRecording,
+    PlayRecording,
+    PlayFlythrough,
+    /// Records a video of the flythrough as PNG frames.
+    RecordVideo,
+    /// Records a video of the flythrough as a Y4M stream.
+    RecordVideoY4m,
+    CycleCamera,
+    ToggleOrthographic,
+    Screenshot,
+    /// Takes a screenshot at a multiple of the window size.
+    HighResScreenshot,
+    DecreaseSensitivity,
+    IncreaseSensitivity,
+    Exit,
+}
+
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("move_forward", Action::MoveForward),
+    ("move_backward", Action::MoveBackward),
+    ("move_left", Action::MoveLeft),
+    ("move_right", Action::MoveRight),
+    ("move_up", Action::MoveUp),
+    ("move_down", Action::MoveDown),
+    ("roll_left", Action::RollLeft),
+    ("roll_right", Action::RollRight),
+    ("sprint", Action::Sprint),
+    ("slow", Action::Slow),
+    ("zoom_modifier", Action::ZoomModifier),
+    ("toggle_mouse_look", Action::ToggleMouseLook),
+    ("hold_mouse_look", Action::HoldMouseLook),
+    ("grab_cursor", Action::GrabCursor),
+    ("select", Action::Select),
+    ("toggle_normals", Action::ToggleNormals),
+    ("toggle_explode", Action::ToggleExplode),
+    ("toggle_animation", Action::ToggleAnimation),
+    ("toggle_recording", Action::ToggleRecording),
+    ("play_recording", Action::PlayRecording),
+    ("play_flythrough", Action::PlayFlythrough),
+    ("record_video", Action::RecordVideo),
+    ("record_video_y4m", Action::RecordVideoY4m),
+    ("cycle_camera", Action::CycleCamera),
+    ("toggle_orthographic", Action::ToggleOrthographic),
+    ("screenshot", Action::Screenshot),
+    ("high_res_screenshot", Action::HighResScreenshot),
+    ("decrease_sensitivity", Action::DecreaseSensitivity),
+    ("increase_sensitivity", Action::IncreaseSensitivity),
+    ("exit", Action::Exit),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    /// Mouse look, positive to the right.
+    LookX,
+    /// Mouse look, positive upwards.
+    LookY,
+    /// Positive zooms out or slows down.
+    Zoom,
+    /// Look rate in pixels of mouse motion per second, for sticks.
+    TurnX,
+    TurnY,
+    /// Analog movement, positive to the right.
+    Strafe,
+    /// Analog movement, positive forwards.
+    Throttle,
+    /// Analog movement, positive upwards.
+    Lift,
+}
+
+const AXIS_NAMES: &[(&str, Axis)] = &[
+    ("look_x", Axis::LookX),
+    ("look_y", Axis::LookY),
+    ("zoom", Axis::Zoom),
+    ("turn_x", Axis::TurnX),
+    ("turn_y", Axis::TurnY),
+    ("strafe", Axis::Strafe),
+    ("throttle", Axis::Throttle),
+    ("lift", Axis::Lift),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+    Logo,
+}
+
+impl Modifier {
+    fn keys(self) -> [VirtualKeyCode; 2] {
+        match self {
+            Self::Shift => [VirtualKeyCode::LShift, VirtualKeyCode::RShift],
+            Self::Control => [VirtualKeyCode::LControl, VirtualKeyCode::RControl],
+            Self::Alt => [VirtualKeyCode::LAlt, VirtualKeyCode::RAlt],
+            Self::Logo => [VirtualKeyCode::LWin, VirtualKeyCode::RWin],
+        }
+    }
+}
+
+/// Anything that can be held down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    /// A key by the symbol it produces in the current layout.
+    Key(VirtualKeyCode),
+    /// A key by its physical position.
+    ScanCode(u32),
+    Mouse(MouseButton),
+    /// Either the left or the right variant of a modifier key.
+    Modifier(Modifier),
+    /// A button on any connected gamepad.
+    Gamepad(GamepadButton),
+}
+
+/// Buttons that must all be held. The last one is the trigger, so `control+r`
+/// fires when R is pressed while Control is held but not the other way round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    buttons: Vec<Button>,
+}
+
+impl Chord {
+    fn trigger(&self) -> Button {
+        self.buttons[self.buttons.len() - 1]
+    }
+}
+
+/// Gamepad buttons by their position on an Xbox style controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+const GAMEPAD_BUTTON_NAMES: &[(&str, GamepadButton)] = &[
+    ("gamepad_south", GamepadButton::South),
+    ("gamepad_east", GamepadButton::East),
+    ("gamepad_north", GamepadButton::North),
+    ("gamepad_west", GamepadButton::West),
+    ("gamepad_left_bumper", GamepadButton::LeftBumper),
+    ("gamepad_right_bumper", GamepadButton::RightBumper),
+    ("gamepad_left_trigger", GamepadButton::LeftTrigger),
+    ("gamepad_right_trigger", GamepadButton::RightTrigger),
+    ("gamepad_select", GamepadButton::Select),
+    ("gamepad_start", GamepadButton::Start),
+    ("gamepad_left_stick", GamepadButton::LeftStick),
+    ("gamepad_right_stick", GamepadButton::RightStick),
+    ("gamepad_dpad_up", GamepadButton::DPadUp),
+    ("gamepad_dpad_down", GamepadButton::DPadDown),
+    ("gamepad_dpad_left", GamepadButton::DPadLeft),
+    ("gamepad_dpad_right", GamepadButton::DPadRight),
+];
+
+/// Gamepad axes after dead zones and response curves. Sticks range from -1 to
+/// 1 with positive Y up, and triggers from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AxisSource {
+    /// Raw mouse motion in pixels, positive to the right.
+    MouseX,
+    /// Raw mouse motion in pixels, positive upwards.
+    MouseY,
+    /// Lines scrolled, positive upwards.
+    MouseWheel,
+    Gamepad(GamepadAxis),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisBinding {
+    source: AxisSource,
+    scale: f32,
+}
+
+/// Bindings from actions and axes to inputs, read from a config file.
+#[derive(Debug, Clone)]
+pub struct InputMap {
+    actions: HashMap<Action, Vec<Chord>>,
+    axes: HashMap<Axis, Vec<AxisBinding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut map = Self {
+            actions: HashMap::new(),
+            axes: HashMap::new(),
+        };
+        map.apply(DEFAULT_BINDINGS)
+            .expect("Invalid default input bindings");
+        map
+    }
+}
+
+impl InputMap {
+    /// Reads the bindings in `path` on top of the defaults. A missing file
+    /// leaves the defaults as they are.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut map = Self::default();
+        match fs::read_to_string(path) {
+            Ok(text) => map.apply(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(map)
+    }
+
+    /// Applies `name = binding, ...` lines, replacing the bindings of every
+    /// action or axis that is named.
+    fn apply(&mut self, text: &str) -> Result<()> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| anyhow!("line {}: {}", i + 1, message);
+            let equals = line
+                .find('=')
+                .ok_or_else(|| error("expected `name = bindings`".to_string()))?;
+            let name = line[..equals].trim();
+            let bindings = line[equals + 1..]
+                .split(',')
+                .map(str::trim)
+                .filter(|binding| !binding.is_empty());
+            if let Some(action) = lookup(ACTION_NAMES, name) {
+                let chords = bindings.map(parse_chord).collect::<Result<_, _>>();
+                self.actions.insert(action, chords.map_err(error)?);
+            } else if let Some(axis) = lookup(AXIS_NAMES, name) {
+                let axis_bindings = bindings.map(parse_axis_binding).collect::<Result<_, _>>();
+                self.axes.insert(axis, axis_bindings.map_err(error)?);
+            } else {
+                return Err(error(format!("unknown action or axis `{}`", name)));
+            }
+        }
+        Ok(())
+    }
+
+    fn chords(&self, action: Action) -> &[Chord] {
+        self.actions.get(&action).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Tracks the state of every input over a frame and answers queries about
+/// actions and axes through an `InputMap`.
+pub struct Input {
+    map: InputMap,
+    held: HashSet<Button>,
+    /// Buttons that went down this frame.
+    pressed: HashSet<Button>,
+    mouse_delta: (f32, f32),
+    scroll_delta: f32,
+    cursor_position: (f64, f64),
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    focused: bool,
+}
+
+impl Input {
+    pub fn new(map: InputMap) -> Self {
+        Self {
+            map,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            mouse_delta: (0.0, 0.0),
+            scroll_delta: 0.0,
+            cursor_position: (0.0, 0.0),
+            gamepad_axes: HashMap::new(),
+            focused: true,
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        scancode,
+                        virtual_keycode,
+                        ..
+                    },
+                ..
+            } => {
+                self.set_button(Button::ScanCode(scancode), state);
+                if let Some(key) = virtual_keycode {
+                    self.set_button(Button::Key(key), state);
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.set_button(Button::Mouse(button), state)
+            }
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::LineDelta(_, dy),
+                ..
+            } => self.scroll_delta += dy,
+            // Touchpads scroll in fractions of a line.
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::PixelDelta(position),
+                ..
+            } => self.scroll_delta += (position.y / PIXELS_PER_SCROLL_LINE) as f32,
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = (position.x, position.y)
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = focused;
+                // Releases that happen while unfocused are never seen.
+                if !focused {
+                    self.held.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = *event {
+            if self.focused {
+                let (x, y) = self.mouse_delta;
+                self.mouse_delta = (x + dx as f32, y - dy as f32);
+            }
+        }
+    }
+
+    /// Presses or releases a gamepad button. Gamepads are read regardless of
+    /// focus, so input is ignored while the window is unfocused.
+    pub fn set_gamepad_button(&mut self, button: GamepadButton, pressed: bool) {
+        let state = if pressed {
+            ElementState::Pressed
+        } else {
+            ElementState::Released
+        };
+        if self.focused || !pressed {
+            self.set_button(Button::Gamepad(button), state);
+        }
+    }
+
+    pub fn set_gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.gamepad_axes.insert(axis, value);
+    }
+
+    /// Releases every gamepad button and centres every axis, for when a
+    /// gamepad is disconnected.
+    pub fn reset_gamepad(&mut self) {
+        self.held
+            .retain(|button| !matches!(button, Button::Gamepad(_)));
+        self.gamepad_axes.clear();
+    }
+
+    /// Clears the per-frame state. Call once all queries for a frame are done.
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
+    }
+
+    /// Returns the cursor position in physical pixels from the top left of the
+    /// window.
+    pub fn cursor_position(&self) -> (f64, f64) {
+        self.cursor_position
+    }
+
+    /// Returns whether any chord bound to `action` is held.
+    pub fn held(&self, action: Action) -> bool {
+        self.map
+            .chords(action)
+            .iter()
+            .any(|chord| self.chord_held(chord))
+    }
+
+    /// Returns whether a chord bound to `action` was completed this frame. A
+    /// chord is ignored while a longer chord with the same trigger, bound to
+    /// any action, is completed too, so `r` does not fire along with
+    /// `control+r`.
+    pub fn pressed(&self, action: Action) -> bool {
+        self.map.chords(action).iter().any(|chord| {
+            self.chord_pressed(chord)
+                && !self.map.actions.values().flatten().any(|other| {
+                    other.buttons.len() > chord.buttons.len()
+                        && other.trigger() == chord.trigger()
+                        && self.chord_pressed(other)
+                })
+        })
+    }
+
+    pub fn axis(&self, axis: Axis) -> f32 {
+        let bindings = self.map.axes.get(&axis).map_or(&[][..], Vec::as_slice);
+        bindings
+            .iter()
+            .map(|binding| {
+                let value = match binding.source {
+                    AxisSource::MouseX => self.mouse_delta.0,
+                    AxisSource::MouseY => self.mouse_delta.1,
+                    AxisSource::MouseWheel => self.scroll_delta,
+                    AxisSource::Gamepad(_) if !self.focused => 0.0,
+                    AxisSource::Gamepad(axis) => {
+                        self.gamepad_axes.get(&axis).copied().unwrap_or(0.0)
+                    }
+                };
+                value * binding.scale
+            })
+            .sum()
+    }
+
+    fn set_button(&mut self, button: Button, state: ElementState) {
+        match state {
+            // Key repeats arrive as more presses, which must not count as new.
+            ElementState::Pressed => {
+                if self.held.insert(button) {
+                    self.pressed.insert(button);
+                }
+            }
+            ElementState::Released => {
+                self.held.remove(&button);
+            }
+        }
+    }
+
+    fn button_held(&self, button: Button) -> bool {
+        match button {
+            Button::Modifier(modifier) => modifier
+                .keys()
+                .iter()
+                .any(|&key| self.held.contains(&Button::Key(key))),
+            _ => self.held.contains(&button),
+        }
+    }
+
+    fn chord_held(&self, chord: &Chord) -> bool {
+        chord.buttons.iter().all(|&button| self.button_held(button))
+    }
+
+    fn chord_pressed(&self, chord: &Chord) -> bool {
+        let trigger_pressed = match chord.trigger() {
+            Button::Modifier(modifier) => modifier
+                .keys()
+                .iter()
+                .any(|&key| self.pressed.contains(&Button::Key(key))),
+            trigger => self.pressed.contains(&trigger),
+        };
+        trigger_pressed && self.chord_held(chord)
+    }
+}
+
+fn lookup<T: Copy>(names: &[(&str, T)], name: &str) -> Option<T> {
+    names
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, value)| value)
+}
+
+fn parse_chord(text: &str) -> Result<Chord, String> {
+    let buttons = text
+        .split('+')
+        .map(|name| parse_button(name.trim()))
+        .collect::<Result<_, _>>()?;
+    Ok(Chord { buttons })
+}
+
+fn parse_button(name: &str) -> Result<Button, String> {
+    let name = name.to_ascii_lowercase();
+    if let Some(code) = name.strip_prefix("scancode:") {
+        return code
+            .parse()
+            .map(Button::ScanCode)
+            .map_err(|_| format!("invalid scancode `{}`", code));
+    }
+    if let Some(button) = name.strip_prefix("mouse_") {
+        return match button {
+            "left" => Ok(Button::Mouse(MouseButton::Left)),
+            "right" => Ok(Button::Mouse(MouseButton::Right)),
+            "middle" => Ok(Button::Mouse(MouseButton::Middle)),
+            _ => button
+                .parse()
+                .map(|n| Button::Mouse(MouseButton::Other(n)))
+                .map_err(|_| format!("unknown mouse button `{}`", name)),
+        };
+    }
+    let modifier = match name.as_str() {
+        "shift" => Some(Modifier::Shift),
+        "control" | "ctrl" => Some(Modifier::Control),
+        "alt" => Some(Modifier::Alt),
+        "logo" => Some(Modifier::Logo),
+        _ => None,
+    };
+    if let Some(modifier) = modifier {
+        return Ok(Button::Modifier(modifier));
+    }
+    if let Some(button) = lookup(GAMEPAD_BUTTON_NAMES, &name) {
+        return Ok(Button::Gamepad(button));
+    }
+    lookup(KEY_NAMES, &name)
+        .map(Button::Key)
+        .ok_or_else(|| format!("unknown button `{}`", name))
+}
+
+fn parse_axis_binding(text: &str) -> Result<AxisBinding, String> {
+    let (source, scale) = match text.find('*') {
+        Some(star) => {
+            let scale = text[star + 1..].trim();
+            let scale = scale
+                .parse()
+                .map_err(|_| format!("invalid axis scale `{}`", scale))?;
+            (text[..star].trim(), scale)
+        }
+        None => (text, 1.0),
+    };
+    let (source, scale) = match source.strip_prefix('-') {
+        Some(source) => (source.trim(), -scale),
+        None => (source, scale),
+    };
+    let source = match source.to_ascii_lowercase().as_str() {
+        "mouse_x" => AxisSource::MouseX,
+        "mouse_y" => AxisSource::MouseY,
+        "mouse_wheel" => AxisSource::MouseWheel,
+        "left_stick_x" => AxisSource::Gamepad(GamepadAxis::LeftStickX),
+        "left_stick_y" => AxisSource::Gamepad(GamepadAxis::LeftStickY),
+        "right_stick_x" => AxisSource::Gamepad(GamepadAxis::RightStickX),
+        "right_stick_y" => AxisSource::Gamepad(GamepadAxis::RightStickY),
+        "left_trigger" => AxisSource::Gamepad(GamepadAxis::LeftTrigger),
+        "right_trigger" => AxisSource::Gamepad(GamepadAxis::RightTrigger),
+        _ => return Err(format!("unknown axis `{}`", source)),
+    };
+    Ok(AxisBinding { source, scale })
+}
+
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = &[
+    ("a", VirtualKeyCode::A),
+    ("b", VirtualKeyCode::B),
+    ("c", VirtualKeyCode::C),
+    ("d", VirtualKeyCode::D),
+    ("e", VirtualKeyCode::E),
+    ("f", VirtualKeyCode::F),
+    ("g", VirtualKeyCode::G),
+    ("h", VirtualKeyCode::H),
+    ("i", VirtualKeyCode::I),
+    ("j", VirtualKeyCode::J),
+    ("k", VirtualKeyCode::K),
+    ("l", VirtualKeyCode::L),
+    ("m", VirtualKeyCode::M),
+    ("n", VirtualKeyCode::N),
+    ("o", VirtualKeyCode::O),
+    ("p", VirtualKeyCode::P),
+    ("q", VirtualKeyCode::Q),
+    ("r", VirtualKeyCode::R),
+    ("s", VirtualKeyCode::S),
+    ("t", VirtualKeyCode::T),
+    ("u", VirtualKeyCode::U),
+    ("v", VirtualKeyCode::V),
+    ("w", VirtualKeyCode::W),
+    ("x", VirtualKeyCode::X),
+    ("y", VirtualKeyCode::Y),
+    ("z", VirtualKeyCode::Z),
+    ("0", VirtualKeyCode::Key0),
+    ("1", VirtualKeyCode::Key1),
+    ("2", VirtualKeyCode::Key2),
+    ("3", VirtualKeyCode::Key3),
+    ("4", VirtualKeyCode::Key4),
+    ("5", VirtualKeyCode::Key5),
+    ("6", VirtualKeyCode::Key6),
+    ("7", VirtualKeyCode::Key7),
+    ("8", VirtualKeyCode::Key8),
+    ("9", VirtualKeyCode::Key9),
+    ("f1", VirtualKeyCode::F1),
+    ("f2", VirtualKeyCode::F2),
+    ("f3", VirtualKeyCode::F3),
+    ("f4", VirtualKeyCode::F4),
+    ("f5", VirtualKeyCode::F5),
+    ("f6", VirtualKeyCode::F6),
+    ("f7", VirtualKeyCode::F7),
+    ("f8", VirtualKeyCode::F8),
+    ("f9", VirtualKeyCode::F9),
+    ("f10", VirtualKeyCode::F10),
+    ("f11", VirtualKeyCode::F11),
+    ("f12", VirtualKeyCode::F12),
+    ("escape", VirtualKeyCode::Escape),
+    ("tab", VirtualKeyCode::Tab),
+    ("space", VirtualKeyCode::Space),
+    ("return", VirtualKeyCode::Return),
+    ("backspace", VirtualKeyCode::Back),
+    ("insert", VirtualKeyCode::Insert),
+    ("delete", VirtualKeyCode::Delete),
+    ("home", VirtualKeyCode::Home),
+    ("end", VirtualKeyCode::End),
+    ("page_up", VirtualKeyCode::PageUp),
+    ("page_down", VirtualKeyCode::PageDown),
+    ("left", VirtualKeyCode::Left),
+    ("right", VirtualKeyCode::Right),
+    ("up", VirtualKeyCode::Up),
+    ("down", VirtualKeyCode::Down),
+    ("lshift", VirtualKeyCode::LShift),
+    ("rshift", VirtualKeyCode::RShift),
+    ("lcontrol", VirtualKeyCode::LControl),
+    ("rcontrol", VirtualKeyCode::RControl),
+    ("lalt", VirtualKeyCode::LAlt),
+    ("ralt", VirtualKeyCode::RAlt),
+    ("lwin", VirtualKeyCode::LWin),
+    ("rwin", VirtualKeyCode::RWin),
+    ("caps_lock", VirtualKeyCode::Capital),
+    ("minus", VirtualKeyCode::Minus),
+    ("equals", VirtualKeyCode::Equals),
+    ("lbracket", VirtualKeyCode::LBracket),
+    ("rbracket", VirtualKeyCode::RBracket),
+    ("semicolon", VirtualKeyCode::Semicolon),
+    ("apostrophe", VirtualKeyCode::Apostrophe),
+    ("grave", VirtualKeyCode::Grave),
+    ("backslash", VirtualKeyCode::Backslash),
+    ("comma", VirtualKeyCode::Comma),
+    ("period", VirtualKeyCode::Period),
+    ("slash", VirtualKeyCode::Slash),
+    ("numpad0", VirtualKeyCode::Numpad0),
+    ("numpad1", VirtualKeyCode::Numpad1),
+    ("numpad2", VirtualKeyCode::Numpad2),
+    ("numpad3", VirtualKeyCode::Numpad3),
+    ("numpad4", VirtualKeyCode::Numpad4),
+    ("numpad5", VirtualKeyCode::Numpad5),
+    ("numpad6", VirtualKeyCode::Numpad6),
+    ("numpad7", VirtualKeyCode::Numpad7),
+    ("numpad8", VirtualKeyCode::Numpad8),
+    ("numpad9", VirtualKeyCode::Numpad9),
+    ("numpad_add", VirtualKeyCode::Add),
+    ("numpad_subtract", VirtualKeyCode::Subtract),
+    ("numpad_enter", VirtualKeyCode::NumpadEnter),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_buttons_by_name() {
+        assert_eq!(parse_button("W"), Ok(Button::Key(VirtualKeyCode::W)));
+        assert_eq!(parse_button("f12"), Ok(Button::Key(VirtualKeyCode::F12)));
+        assert_eq!(parse_button("scancode:17"), Ok(Button::ScanCode(17)));
+        assert_eq!(
+            parse_button("mouse_left"),
+            Ok(Button::Mouse(MouseButton::Left))
+        );
+        assert_eq!(
+            parse_button("mouse_4"),
+            Ok(Button::Mouse(MouseButton::Other(4)))
+        );
+        assert_eq!(
+            parse_button("ctrl"),
+            Ok(Button::Modifier(Modifier::Control))
+        );
+        assert_eq!(
+            parse_button("Gamepad_South"),
+            Ok(Button::Gamepad(GamepadButton::South))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_buttons() {
+        assert!(parse_button("").is_err());
+        assert!(parse_button("hyper").is_err());
+        assert!(parse_button("scancode:x").is_err());
+        assert!(parse_button("scancode:-1").is_err());
+        assert!(parse_button("mouse_sideways").is_err());
+    }
+
+    #[test]
+    fn parses_chords_with_the_trigger_last() {
+        let chord = parse_chord("shift + f12").unwrap();
+        assert_eq!(
+            chord.buttons,
+            vec![
+                Button::Modifier(Modifier::Shift),
+                Button::Key(VirtualKeyCode::F12)
+            ]
+        );
+        assert_eq!(chord.trigger(), Button::Key(VirtualKeyCode::F12));
+        assert!(parse_chord("control+").is_err());
+        assert!(parse_chord("control+nope").is_err());
+    }
+
+    #[test]
+    fn parses_axis_bindings_with_sign_and_scale() {
+        assert_eq!(
+            parse_axis_binding("mouse_x"),
+            Ok(AxisBinding {
+                source: AxisSource::MouseX,
+                scale: 1.0
+            })
+        );
+        assert_eq!(
+            parse_axis_binding("-left_stick_y * 0.5"),
+            Ok(AxisBinding {
+                source: AxisSource::Gamepad(GamepadAxis::LeftStickY),
+                scale: -0.5
+            })
+        );
+        assert_eq!(
+            parse_axis_binding("- Mouse_Wheel*2"),
+            Ok(AxisBinding {
+                source: AxisSource::MouseWheel,
+                scale: -2.0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_axis_bindings() {
+        assert!(parse_axis_binding("mouse_z").is_err());
+        assert!(parse_axis_binding("mouse_x * fast").is_err());
+        assert!(parse_axis_binding("mouse_x *").is_err());
+        assert!(parse_axis_binding("").is_err());
+    }
+
+    #[test]
+    fn default_bindings_parse() {
+        let map = InputMap::default();
+        assert!(!map.chords(Action::Exit).is_empty());
+    }
+
+    #[test]
+    fn config_lines_replace_bindings() {
+        let mut map = InputMap::default();
+        map.apply("# comment\n\nexit = q, control+w\n").unwrap();
+        assert_eq!(map.chords(Action::Exit).len(), 2);
+        map.apply("exit =").unwrap();
+        assert!(map.chords(Action::Exit).is_empty());
+    }
+
+    #[test]
+    fn reports_the_line_of_config_errors() {
+        let mut map = InputMap::default();
+        let error = map.apply("exit = q\nexit q").unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"));
+        assert!(map.apply("jump = space").is_err());
+        assert!(map.apply("turn_x = mouse_z").is_err());
+    }
+}
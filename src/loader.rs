@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset_manager::AssetManager;
+use crate::model::{Model, ModelData, ModelUpload};
+use crate::texture::{Texture, TextureData};
+
+const MAX_WORKERS: usize = 4;
+
+/// Identifies a load started with `Loader::load_model` or
+/// `Loader::load_texture_array`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadId(usize);
+
+/// An asset returned by `Loader::upload`.
+pub enum Loaded {
+    Model(Rc<Model>),
+    Texture(Rc<Texture>),
+}
+
+enum Decoded {
+    Model(ModelData),
+    TextureArray(Vec<TextureData>),
+}
+
+enum Upload {
+    Model(ModelUpload),
+    TextureArray(Vec<TextureData>),
+}
+
+type Job = (LoadId, Box<dyn FnOnce() -> Result<Decoded> + Send>);
+
+/// Loads assets in the background. Files are read and decoded on worker
+/// threads, and `upload` moves the results to the GPU a little at a time on
+/// the thread owning the GL context.
+pub struct Loader {
+    jobs: Option<Sender<Job>>,
+    decoded: Receiver<(LoadId, Result<Decoded>)>,
+    workers: Vec<JoinHandle<()>>,
+    uploads: VecDeque<(LoadId, Upload)>,
+    /// Loads of models that were already in the `AssetManager`.
+    cached: Vec<(LoadId, Rc<Model>)>,
+    next_id: usize,
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (decoded_sender, decoded) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let worker_count = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_WORKERS);
+        let workers = (0..worker_count)
+            .map(|_| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let decoded_sender = decoded_sender.clone();
+                thread::spawn(move || loop {
+                    // The lock is only held while waiting, not while decoding.
+                    let job = job_receiver.lock().unwrap().recv();
+                    let (id, decode) = match job {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    if decoded_sender.send((id, decode())).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            decoded,
+            workers,
+            uploads: VecDeque::new(),
+            cached: vec![],
+            next_id: 0,
+        }
+    }
+
+    /// Queues the model at `path` for loading, unless it is in `assets`
+    /// already. It is returned by `upload` under the returned id once ready.
+    pub fn load_model<P>(&mut self, assets: &AssetManager, path: P) -> Result<LoadId>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        if let Some(model) = assets.model(&path) {
+            let id = self.next_id();
+            self.cached.push((id, model));
+            return Ok(id);
+        }
+        self.queue(move || {
+            let data = ModelData::load(&path)
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+            Ok(Decoded::Model(data))
+        })
+    }
+
+    /// Queues a texture array whose layers are made by `decode` on a worker
+    /// thread, see `Texture::upload_array`.
+    pub fn load_texture_array<F>(&mut self, decode: F) -> Result<LoadId>
+    where
+        F: FnOnce() -> Result<Vec<TextureData>> + Send + 'static,
+    {
+        self.queue(move || decode().map(Decoded::TextureArray))
+    }
+
+    /// Uploads decoded assets until about `budget` bytes have been sent to the
+    /// GPU, always making some progress, and returns the assets finished and
+    /// the loads that failed. Finished models are added to `assets`.
+    pub unsafe fn upload(
+        &mut self,
+        assets: &mut AssetManager,
+        budget: usize,
+    ) -> Vec<(LoadId, Result<Loaded>)> {
+        let mut finished: Vec<_> = self
+            .cached
+            .drain(..)
+            .map(|(id, model)| (id, Ok(Loaded::Model(model))))
+            .collect();
+        for (id, decoded) in self.decoded.try_iter() {
+            let upload = match decoded {
+                Ok(Decoded::Model(data)) => Upload::Model(ModelUpload::new(data)),
+                Ok(Decoded::TextureArray(layers)) => Upload::TextureArray(layers),
+                Err(e) => {
+                    finished.push((id, Err(e)));
+                    continue;
+                }
+            };
+            self.uploads.push_back((id, upload));
+        }
+
+        let mut uploaded = 0;
+        while let Some((_, upload)) = self.uploads.front_mut() {
+            let done = match upload {
+                Upload::Model(upload) => upload.is_done(),
+                Upload::TextureArray(_) => false,
+            };
+            if !done {
+                if uploaded > 0 && uploaded >= budget {
+                    break;
+                }
+                match upload {
+                    Upload::Model(upload) => {
+                        uploaded += upload.step(assets).max(1);
+                        continue;
+                    }
+                    // Texture arrays are uploaded whole.
+                    Upload::TextureArray(layers) => {
+                        uploaded += layers.iter().map(TextureData::size).sum::<usize>().max(1);
+                    }
+                }
+            }
+            let (id, upload) = self.uploads.pop_front().unwrap();
+            let loaded = match upload {
+                Upload::Model(upload) => Ok(Loaded::Model(upload.finish(assets))),
+                Upload::TextureArray(layers) => {
+                    Texture::upload_array(&layers).map(|texture| Loaded::Texture(Rc::new(texture)))
+                }
+            };
+            finished.push((id, loaded));
+        }
+        finished
+    }
+
+    /// Fails if every worker has stopped.
+    fn queue<F>(&mut self, decode: F) -> Result<LoadId>
+    where
+        F: FnOnce() -> Result<Decoded> + Send + 'static,
+    {
+        let id = self.next_id();
+        let job = (id, Box::new(decode) as Box<_>);
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or_else(|| anyhow!("No loader threads are left to load with"))?;
+        Ok(id)
+    }
+
+    fn next_id(&mut self) -> LoadId {
+        let id = LoadId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        // Closing the job channel stops the workers once their current job is
+        // done.
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            worker.join().ok();
+        }
+    }
+}
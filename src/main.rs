@@ -1,271 +1,687 @@
+mod asset_manager;
+mod asteroids;
+mod bcn;
+mod buffer;
 mod camera;
+mod camera_path;
+mod capabilities;
+mod compressed_texture;
+mod effects;
+mod gamepad;
+mod input;
+mod loader;
 mod model;
+mod orbit_camera;
+mod picking;
+mod procedural;
+mod sampler;
+mod screenshot;
+mod shader_error;
 mod shader_program;
 mod texture;
+mod texture_units;
+mod video;
 
-use std::ffi::c_void;
-use std::mem;
+use std::rc::Rc;
 use std::time::Instant;
 
 use gl::types::*;
 use glutin::{Api, ContextBuilder, GlProfile, GlRequest};
 use nalgebra_glm as glm;
-use rand::prelude::*;
 use winit::{
     dpi::LogicalSize,
-    event::{DeviceEvent, ElementState, Event, MouseScrollDelta, VirtualKeyCode, WindowEvent},
+    event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
-use camera::{Camera, CameraMotion};
-use model::Model;
-use shader_program::ShaderProgram;
+use asset_manager::AssetManager;
+use asteroids::{Animation, AsteroidField};
+use camera::{Camera, CameraController, CameraMotion, Projection, INITIAL_FOV};
+use camera_path::{CameraPose, CameraRecording, Playback, SplinePath};
+use effects::{Explode, NormalVisualizer, Outline};
+use gamepad::Gamepads;
+use input::{Action, Axis, Input, InputMap};
+use loader::{LoadId, Loaded, Loader};
+use model::{Model, MAX_ANISOTROPY};
+use orbit_camera::{OrbitCamera, OrbitMode};
+use picking::{Pick, Picker};
+use procedural::{Gradient, Noise};
+use screenshot::{Readback, TiledCapture};
+use shader_error::ShaderError;
+use texture::{Channels, Texture, TextureData, TextureOptions};
+use texture_units::TextureUnits;
+use video::{VideoFormat, VideoRecorder};
 
 const VERTEX_SHADER: &str = include_str!("shaders/main.vert");
 const FRAGMENT_SHADER: &str = include_str!("shaders/main.frag");
+const PLANET_FRAGMENT_SHADER: &str = include_str!("shaders/planet.frag");
 
 const INSTANCED_VERTEX_SHADER: &str = include_str!("shaders/instanced.vert");
+const LAYERED_FRAGMENT_SHADER: &str = include_str!("shaders/layered.frag");
+
+const WINDOW_TITLE: &str = "Learn OpenGL";
+
+const SHADER_CACHE_DIR: &str = "shader_cache";
+
+const NEAR_PLANE: f32 = 0.1;
+/// Only used when reversed-Z is unsupported, otherwise the far plane is at
+/// infinity.
+const FAR_PLANE: f32 = 1000.0;
+
+/// Factor by which the bracket keys change the mouse sensitivity.
+const SENSITIVITY_STEP: f32 = 1.25;
+
+/// Optional bindings read on top of the defaults in `src/input.cfg`.
+const INPUT_CONFIG_PATH: &str = "input.cfg";
+
+/// Actions that move the camera while held.
+const MOTION_ACTIONS: &[(Action, CameraMotion)] = &[
+    (Action::MoveForward, CameraMotion::Forward),
+    (Action::MoveBackward, CameraMotion::Backward),
+    (Action::MoveLeft, CameraMotion::Left),
+    (Action::MoveRight, CameraMotion::Right),
+    (Action::MoveUp, CameraMotion::Up),
+    (Action::MoveDown, CameraMotion::Down),
+    (Action::RollLeft, CameraMotion::RollLeft),
+    (Action::RollRight, CameraMotion::RollRight),
+    (Action::Sprint, CameraMotion::Sprint),
+    (Action::Slow, CameraMotion::Slow),
+];
+
+/// Analog axes that move the camera, with the motions for positive and
+/// negative values.
+const MOTION_AXES: &[(Axis, CameraMotion, CameraMotion)] = &[
+    (Axis::Strafe, CameraMotion::Right, CameraMotion::Left),
+    (
+        Axis::Throttle,
+        CameraMotion::Forward,
+        CameraMotion::Backward,
+    ),
+    (Axis::Lift, CameraMotion::Up, CameraMotion::Down),
+];
+
+const CAMERA_RECORDING_PATH: &str = "recordings/camera.txt";
+const FLYTHROUGH_PATH: &str = "resources/paths/asteroid_flythrough.txt";
+const FLYTHROUGH_SPEED: f32 = 40.0;
+
+const SCREENSHOT_DIR: &str = "screenshots";
+/// Multiple of the window size that high resolution screenshots are taken at.
+const HIGH_RES_SCREENSHOT_SCALE: u32 = 4;
+
+const VIDEO_DIR: &str = "videos";
+const VIDEO_FRAME_RATE: u32 = 60;
+
+/// Object ids written by the picking pass.
+const PLANET_ID: u32 = 1;
+const ASTEROIDS_ID: u32 = 2;
 
 const MULTISAMPLING_SAMPLES: u16 = 4;
 
 const ASTEROID_COUNT: usize = 100000;
 
+const PLANET_PATH: &str = "resources/models/planet/planet.obj";
+const ASTEROID_PATH: &str = "resources/models/rock/rock.obj";
+const ASTEROID_TEXTURE_PATH: &str = "resources/models/rock/rock.png";
+/// Tints of the asteroid texture array layers.
+const ASTEROID_TINTS: &[[f32; 3]] = &[
+    [1.0, 1.0, 1.0],
+    [1.0, 0.8, 0.65],
+    [0.75, 0.75, 0.8],
+    [0.9, 0.85, 0.7],
+];
+/// Color gradients of the procedural asteroid textures, from the lowest to the
+/// highest noise values.
+const ASTEROID_NOISE_GRADIENTS: &[&[(f32, [f32; 3])]] = &[
+    &[
+        (0.2, [0.12, 0.1, 0.09]),
+        (0.5, [0.35, 0.3, 0.26]),
+        (0.8, [0.6, 0.56, 0.5]),
+    ],
+    &[
+        (0.25, [0.2, 0.08, 0.05]),
+        (0.55, [0.45, 0.25, 0.15]),
+        (0.75, [0.55, 0.45, 0.38]),
+    ],
+];
+/// Size of the procedural detail texture tiled over the planet surface.
+const PLANET_DETAIL_SIZE: u32 = 256;
+/// Bytes of texture and mesh data uploaded per frame while models load.
+const UPLOAD_BUDGET: usize = 4 * 1024 * 1024;
+
 fn main() {
     let event_loop = EventLoop::new();
     let window_builder = WindowBuilder::new()
-        .with_title("Learn OpenGL")
+        .with_title(WINDOW_TITLE)
         .with_inner_size(LogicalSize::new(800, 600));
-    let context = ContextBuilder::new()
-        .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
-        .with_gl_profile(GlProfile::Core)
-        .with_vsync(true)
-        .with_multisampling(MULTISAMPLING_SAMPLES)
-        .build_windowed(window_builder, &event_loop)
-        .unwrap();
+    // Compute shaders need GL 4.3; without them the asteroids are animated on the CPU.
+    let context = [(4, 3), (3, 3)]
+        .iter()
+        .find_map(|&version| {
+            ContextBuilder::new()
+                .with_gl(GlRequest::Specific(Api::OpenGl, version))
+                .with_gl_profile(GlProfile::Core)
+                .with_vsync(true)
+                .with_multisampling(MULTISAMPLING_SAMPLES)
+                .with_stencil_buffer(8)
+                .build_windowed(window_builder.clone(), &event_loop)
+                .ok()
+        })
+        .expect("Failed to create an OpenGL 3.3 context");
     let context = unsafe { context.make_current().unwrap() };
-    context.window().set_cursor_grab(true).unwrap();
-    context.window().set_cursor_visible(false);
+    set_cursor_grab(context.window(), true);
     let mut window_size = context.window().inner_size();
 
     gl::load_with(|s| context.get_proc_address(s));
+    compressed_texture::detect_supported_formats();
     unsafe {
         gl::Enable(gl::DEPTH_TEST);
         gl::Enable(gl::MULTISAMPLE);
-        gl::ClearColor(0.1, 0.1, 0.1, 1.0);
+        // Shading happens in linear space and is encoded to sRGB on output.
+        gl::Enable(gl::FRAMEBUFFER_SRGB);
+        gl::ClearColor(0.01, 0.01, 0.01, 1.0);
         gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
         context.swap_buffers().unwrap();
     }
 
-    let mut rng = rand::thread_rng();
-    let radius = 150.0;
-    let offset = 25.0;
-    let mut asteroid_models = Vec::with_capacity(ASTEROID_COUNT);
-    for i in 0..ASTEROID_COUNT {
-        let mut model = glm::Mat4::identity();
-
-        let angle = i as f32 / ASTEROID_COUNT as f32 * 360.0;
-        let x = angle.sin() * radius + rng.gen_range(-offset, offset);
-        let y = rng.gen_range(-offset, offset) * 0.4;
-        let z = angle.cos() * radius + rng.gen_range(-offset, offset);
-        model = glm::translate(&model, &glm::vec3(x, y, z));
-
-        let scale = rng.gen_range(0.05, 0.25);
-        model = glm::scale(&model, &glm::vec3(scale, scale, scale));
-
-        let rotation = rng.gen_range(0, 360) as f32;
-        model = glm::rotate(&model, rotation, &glm::vec3(0.4, 0.6, 0.8));
-
-        asteroid_models.push(model);
-    }
-
-    let planet = unsafe { Model::load("resources/models/planet/planet.obj").unwrap() };
-    let main_shader = ShaderProgram::new(VERTEX_SHADER, FRAGMENT_SHADER, None).unwrap();
+    // Placeholders are drawn until the models have loaded in the background.
+    let mut assets = AssetManager::new(SHADER_CACHE_DIR);
+    let mut loader = Loader::new();
+    let planet_load = queued(loader.load_model(&assets, PLANET_PATH));
+    let asteroid_load = queued(loader.load_model(&assets, ASTEROID_PATH));
+    let asteroid_textures_load = queued(loader.load_texture_array(asteroid_textures));
+    // Model loads that have not finished or failed yet. The placeholder is
+    // unloaded once there are none left.
+    let mut pending_models = [planet_load, asteroid_load]
+        .iter()
+        .filter(|load| load.is_some())
+        .count();
+    let mut planet = unsafe { Model::placeholder(&mut assets) };
+    let planet_shader = expect_shader(assets.shader(VERTEX_SHADER, PLANET_FRAGMENT_SHADER, None));
+    let planet_detail = Rc::new(unsafe { Texture::upload(&planet_detail()) });
 
-    let asteroid = unsafe { Model::load("resources/models/rock/rock.obj").unwrap() };
+    let asteroid = unsafe { Model::placeholder(&mut assets) };
+    let mut asteroid_field = expect_shader(unsafe { AsteroidField::new(asteroid, ASTEROID_COUNT) });
     let instanced_shader =
-        ShaderProgram::new(INSTANCED_VERTEX_SHADER, FRAGMENT_SHADER, None).unwrap();
-
-    let mut buffer = 0;
-    unsafe {
-        gl::GenBuffers(1, &mut buffer);
-        gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            (mem::size_of::<glm::Mat4>() * asteroid_models.len()) as GLsizeiptr,
-            asteroid_models.as_ptr() as *const c_void,
-            gl::STATIC_DRAW,
-        );
+        expect_shader(assets.shader(INSTANCED_VERTEX_SHADER, FRAGMENT_SHADER, None));
+    let layered_shader =
+        expect_shader(assets.shader(INSTANCED_VERTEX_SHADER, LAYERED_FRAGMENT_SHADER, None));
 
-        for mesh in asteroid.meshes.iter() {
-            gl::BindVertexArray(mesh.vao);
-            gl::VertexAttribPointer(
-                3,
-                4,
-                gl::FLOAT,
-                gl::FALSE,
-                mem::size_of::<glm::Mat4>() as GLsizei,
-                0 as *const c_void,
-            );
-            gl::EnableVertexAttribArray(3);
-            gl::VertexAttribDivisor(3, 1);
-            gl::VertexAttribPointer(
-                4,
-                4,
-                gl::FLOAT,
-                gl::FALSE,
-                mem::size_of::<glm::Mat4>() as GLsizei,
-                mem::size_of::<glm::Vec4>() as *const c_void,
-            );
-            gl::EnableVertexAttribArray(4);
-            gl::VertexAttribDivisor(4, 1);
-            gl::VertexAttribPointer(
-                5,
-                4,
-                gl::FLOAT,
-                gl::FALSE,
-                mem::size_of::<glm::Mat4>() as GLsizei,
-                (2 * mem::size_of::<glm::Vec4>()) as *const c_void,
-            );
-            gl::EnableVertexAttribArray(5);
-            gl::VertexAttribDivisor(5, 1);
-            gl::VertexAttribPointer(
-                6,
-                4,
-                gl::FLOAT,
-                gl::FALSE,
-                mem::size_of::<glm::Mat4>() as GLsizei,
-                (3 * mem::size_of::<glm::Vec4>()) as *const c_void,
-            );
-            gl::EnableVertexAttribArray(6);
-            gl::VertexAttribDivisor(6, 1);
-        }
-    }
+    let normal_visualizer = expect_shader(NormalVisualizer::new());
+    let explode = expect_shader(Explode::new());
+    let outline = expect_shader(Outline::new());
+    let mut picker = expect_shader(unsafe { Picker::new(window_size.width, window_size.height) });
+    let mut texture_units = unsafe { TextureUnits::new() };
 
     let mut prev_frame_time = Instant::now();
     let mut delta_time = 0.0f32;
     let mut time = delta_time;
-    let mut pressed_keys = Vec::with_capacity(10);
-    let mut mouse_delta = (0.0, 0.0);
-    let mut scroll_delta = 0.0;
-    let mut camera = Camera::new(
+    let input_map = InputMap::load(INPUT_CONFIG_PATH).unwrap_or_else(|e| {
+        eprintln!(
+            "Failed to load {}, using the default bindings: {}",
+            INPUT_CONFIG_PATH, e
+        );
+        InputMap::default()
+    });
+    let mut input = Input::new(input_map);
+    let mut gamepads = Gamepads::new();
+    let mut window_is_focused = true;
+    let mut mouse_look = true;
+    let mut cursor_grabbed = true;
+    // Set when focus is lost, until the cursor is grabbed again by clicking.
+    let mut grab_suspended = false;
+    let mut projection = Projection::perspective(
+        INITIAL_FOV,
+        window_size.width as f32 / window_size.height as f32,
+        NEAR_PLANE,
+        FAR_PLANE,
+    );
+    if capabilities::clip_control_supported() {
+        projection.far = f32::INFINITY;
+        projection.reversed_z = true;
+    }
+    let mut fps_camera = Camera::new(
         glm::vec3(0.0, 0.0, 3.0),
         glm::vec3(0.0, 1.0, 0.0),
         -90.0,
         0.0,
+        projection,
     );
-    let mut window_is_focused = true;
+    let mut orbit_camera = OrbitCamera::new(
+        glm::vec3(0.0, -3.0, 0.0),
+        20.0,
+        glm::vec3(0.0, 1.0, 0.0),
+        projection,
+    );
+    let mut use_orbit_camera = false;
+    let mut show_normals = false;
+    let mut explode_start_time = None;
+    let mut stats_time = 0.0;
+    let mut recording: Option<CameraRecording> = None;
+    let mut playback: Option<Playback> = None;
+    let mut selection: Option<Pick> = None;
+    let mut screenshots: Vec<Readback> = vec![];
+    // The scene is frozen while the tiles of a high resolution screenshot are
+    // drawn.
+    let mut tiled_capture: Option<TiledCapture> = None;
+    // Replaces the measured frame time with a fixed timestep while recording.
+    let mut video: Option<VideoRecorder> = None;
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                WindowEvent::Resized(size) => {
-                    window_size = size;
-                    unsafe {
-                        gl::Viewport(0, 0, size.width as GLint, size.height as GLint);
+            Event::WindowEvent { event, .. } => {
+                input.handle_window_event(&event);
+                match event {
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Resized(size) => {
+                        unsafe {
+                            gl::Viewport(0, 0, size.width as GLint, size.height as GLint);
+                            picker.resize(size.width, size.height);
+                        }
+                        window_size = size;
+                        if size.height > 0 {
+                            let aspect = size.width as f32 / size.height as f32;
+                            fps_camera.projection_mut().aspect = aspect;
+                            orbit_camera.projection_mut().aspect = aspect;
+                        }
+                    }
+                    WindowEvent::Focused(is_focused) => {
+                        window_is_focused = is_focused;
+                        if !is_focused {
+                            grab_suspended = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::DeviceEvent { event, .. } => input.handle_device_event(&event),
+            Event::MainEventsCleared => {
+                let now = Instant::now();
+                delta_time = if tiled_capture.is_some() {
+                    0.0
+                } else if let Some(video) = &video {
+                    video.delta_time()
+                } else {
+                    (now - prev_frame_time).as_secs_f32()
+                };
+                time += delta_time;
+                prev_frame_time = now;
+
+                gamepads.poll(&mut input);
+                if input.pressed(Action::Exit) {
+                    *control_flow = ControlFlow::Exit;
+                }
+                if input.pressed(Action::ToggleMouseLook) {
+                    mouse_look = !mouse_look;
+                }
+                // The click that grabs the cursor again does nothing else.
+                let mut clicked_to_grab = false;
+                if input.pressed(Action::GrabCursor) && grab_suspended {
+                    grab_suspended = false;
+                    clicked_to_grab = true;
+                }
+                let grab = window_is_focused
+                    && ((mouse_look && !grab_suspended) || input.held(Action::HoldMouseLook));
+                if grab != cursor_grabbed {
+                    set_cursor_grab(context.window(), grab);
+                    cursor_grabbed = grab;
+                }
+                if input.pressed(Action::ToggleNormals) {
+                    show_normals = !show_normals;
+                }
+                if input.pressed(Action::ToggleExplode) {
+                    explode_start_time = match explode_start_time {
+                        Some(_) => None,
+                        None => Some(time),
                     }
                 }
-                WindowEvent::KeyboardInput { input, .. } => match input.state {
-                    ElementState::Pressed => match input.virtual_keycode {
-                        Some(VirtualKeyCode::Escape) => *control_flow = ControlFlow::Exit,
-                        Some(key) => {
-                            if !pressed_keys.contains(&key) {
-                                pressed_keys.push(key)
+                if input.pressed(Action::CycleCamera) {
+                    // Cycles fly -> free flight -> turntable -> arcball.
+                    if use_orbit_camera {
+                        match orbit_camera.mode() {
+                            OrbitMode::Turntable => orbit_camera.set_mode(OrbitMode::Arcball),
+                            OrbitMode::Arcball => use_orbit_camera = false,
+                        }
+                    } else if fps_camera.free_flight() {
+                        fps_camera.set_free_flight(false);
+                        use_orbit_camera = true;
+                        orbit_camera.set_mode(OrbitMode::Turntable);
+                    } else {
+                        fps_camera.set_free_flight(true);
+                    }
+                }
+                if input.pressed(Action::ToggleOrthographic) {
+                    let projection = if use_orbit_camera {
+                        orbit_camera.projection_mut()
+                    } else {
+                        fps_camera.projection_mut()
+                    };
+                    projection.orthographic = !projection.orthographic;
+                }
+                if input.pressed(Action::DecreaseSensitivity) {
+                    fps_camera.settings_mut().sensitivity /= SENSITIVITY_STEP;
+                }
+                if input.pressed(Action::IncreaseSensitivity) {
+                    fps_camera.settings_mut().sensitivity *= SENSITIVITY_STEP;
+                }
+                if input.pressed(Action::ToggleAnimation) {
+                    let animation = match asteroid_field.animation() {
+                        Animation::Cpu => Animation::Gpu,
+                        Animation::Gpu => Animation::Cpu,
+                    };
+                    if !unsafe { asteroid_field.set_animation(animation) } {
+                        eprintln!("Compute shaders are unsupported, keeping CPU animation");
+                    }
+                }
+                let take_screenshot = input.pressed(Action::Screenshot);
+                if input.pressed(Action::HighResScreenshot) && tiled_capture.is_none() {
+                    tiled_capture = Some(TiledCapture::new(
+                        HIGH_RES_SCREENSHOT_SCALE,
+                        window_size.width,
+                        window_size.height,
+                    ));
+                }
+                if input.pressed(Action::ToggleRecording) {
+                    match recording.take() {
+                        Some(recording) => match recording.save(CAMERA_RECORDING_PATH) {
+                            Ok(()) => {
+                                println!("Saved camera recording to {}", CAMERA_RECORDING_PATH)
                             }
+                            Err(e) => eprintln!("Failed to save camera recording: {}", e),
+                        },
+                        None => {
+                            use_orbit_camera = false;
+                            recording = Some(CameraRecording::new());
+                            println!("Recording camera");
                         }
-                        _ => {}
-                    },
-                    ElementState::Released => match input.virtual_keycode {
-                        Some(key) => {
-                            if let Some(i) = pressed_keys.iter().position(|&k| k == key) {
-                                pressed_keys.swap_remove(i);
+                    }
+                }
+                let play_recording = input.pressed(Action::PlayRecording);
+                let play_flythrough = input.pressed(Action::PlayFlythrough);
+                if play_recording || play_flythrough {
+                    if let Some(playback) = playback.take() {
+                        println!("Playback stopped: {}", playback.report());
+                    } else if play_recording {
+                        match CameraRecording::load(CAMERA_RECORDING_PATH) {
+                            Ok(path) if !path.is_empty() => {
+                                playback = Some(Playback::new(Box::new(path)))
                             }
+                            Ok(_) => eprintln!("Camera recording is empty"),
+                            Err(e) => eprintln!("Failed to load camera recording: {}", e),
                         }
-                        _ => {}
-                    },
-                },
-                WindowEvent::MouseWheel {
-                    delta: MouseScrollDelta::LineDelta(_, dy),
-                    ..
-                } => {
-                    scroll_delta -= dy;
+                    } else {
+                        playback = load_flythrough();
+                    }
                 }
-                WindowEvent::Focused(is_focused) => window_is_focused = is_focused,
-                _ => {}
-            },
-            Event::DeviceEvent { event, .. } => match event {
-                DeviceEvent::MouseMotion {
-                    delta: (dx, dy), ..
-                } => {
-                    if !window_is_focused {
-                        return;
+                let record_video = input.pressed(Action::RecordVideo);
+                let record_video_y4m = input.pressed(Action::RecordVideoY4m);
+                if record_video || record_video_y4m {
+                    if let Some(video) = video.take() {
+                        finish_video(video);
+                    } else {
+                        let format = if record_video_y4m {
+                            VideoFormat::Y4m
+                        } else {
+                            VideoFormat::Png
+                        };
+                        match VideoRecorder::new(
+                            format,
+                            VIDEO_DIR.as_ref(),
+                            window_size.width,
+                            window_size.height,
+                            VIDEO_FRAME_RATE,
+                        ) {
+                            Ok(recorder) => {
+                                // The recording ends with the flythrough.
+                                if playback.is_none() {
+                                    playback = load_flythrough();
+                                }
+                                println!("Recording video");
+                                video = Some(recorder);
+                            }
+                            Err(e) => eprintln!("Failed to start video: {}", e),
+                        }
                     }
-                    let (x, y) = mouse_delta;
-                    mouse_delta = (x + dx as f32, y - dy as f32);
                 }
-                _ => {}
-            },
-            Event::MainEventsCleared => {
-                let now = Instant::now();
-                delta_time = (now - prev_frame_time).as_secs_f32();
-                time += delta_time;
-                prev_frame_time = now;
 
-                let camera_directions: Vec<_> = pressed_keys
+                for (id, model) in unsafe { loader.upload(&mut assets, UPLOAD_BUDGET) } {
+                    let is_model = Some(id) == planet_load || Some(id) == asteroid_load;
+                    match model {
+                        Ok(Loaded::Model(model)) if Some(id) == planet_load => planet = model,
+                        Ok(Loaded::Model(model)) if Some(id) == asteroid_load => unsafe {
+                            asteroid_field.set_model(model)
+                        },
+                        Ok(Loaded::Texture(texture)) if Some(id) == asteroid_textures_load => unsafe {
+                            asteroid_field.set_texture_array(texture)
+                        },
+                        Ok(_) => {}
+                        Err(e) => eprintln!("{:#}", e),
+                    }
+                    if is_model {
+                        pending_models -= 1;
+                        if pending_models == 0 {
+                            Model::unload_placeholder(&mut assets);
+                            println!("Assets: {}", assets.memory_usage());
+                        }
+                    }
+                }
+
+                unsafe {
+                    asteroid_field.update(delta_time);
+                }
+                stats_time += delta_time;
+                if stats_time >= 1.0 {
+                    let asteroids = match asteroid_field.animation() {
+                        Animation::Cpu => {
+                            let stats = asteroid_field.take_stats();
+                            format!(
+                                "CPU asteroids: update {:.2} ms, upload {:.2} ms",
+                                stats.average_update_time().as_secs_f32() * 1000.0,
+                                stats.average_upload_time().as_secs_f32() * 1000.0,
+                            )
+                        }
+                        Animation::Gpu => "GPU asteroids".to_string(),
+                    };
+                    let settings = fps_camera.settings();
+                    context.window().set_title(&format!(
+                        "{} - {} - speed {:.1}, sensitivity {:.3}",
+                        WINDOW_TITLE, asteroids, settings.speed, settings.sensitivity
+                    ));
+                    stats_time = 0.0;
+                }
+
+                let mut camera_directions: Vec<_> = MOTION_ACTIONS
                     .iter()
-                    .filter_map(|key| match key {
-                        VirtualKeyCode::W => Some(CameraMotion::Forward),
-                        VirtualKeyCode::S => Some(CameraMotion::Backward),
-                        VirtualKeyCode::A => Some(CameraMotion::Left),
-                        VirtualKeyCode::D => Some(CameraMotion::Right),
-                        VirtualKeyCode::Space => Some(CameraMotion::Up),
-                        VirtualKeyCode::X => Some(CameraMotion::Down),
-                        _ => None,
-                    })
+                    .filter(|&&(action, _)| input.held(action))
+                    .map(|&(_, motion)| (motion, 1.0))
                     .collect();
-                camera.move_(&camera_directions, delta_time);
-                camera.look(mouse_delta);
-                mouse_delta = (0.0, 0.0);
-                camera.zoom(scroll_delta);
-                scroll_delta = 0.0;
+                for &(axis, positive, negative) in MOTION_AXES {
+                    let value = input.axis(axis).clamp(-1.0, 1.0);
+                    if value > 0.0 {
+                        camera_directions.push((positive, value));
+                    } else if value < 0.0 {
+                        camera_directions.push((negative, -value));
+                    }
+                }
+                // A free cursor is for pointing, not looking around.
+                let (look_x, look_y) = if cursor_grabbed {
+                    (input.axis(Axis::LookX), input.axis(Axis::LookY))
+                } else {
+                    (0.0, 0.0)
+                };
+                let mouse_delta = (
+                    look_x + input.axis(Axis::TurnX) * delta_time,
+                    look_y + input.axis(Axis::TurnY) * delta_time,
+                );
+                // Scrolling sets the flying speed, or zooms while Alt is held.
+                let scroll_delta = input.axis(Axis::Zoom);
+                let zoom_delta = if use_orbit_camera || input.held(Action::ZoomModifier) {
+                    scroll_delta
+                } else {
+                    fps_camera.scale_speed(scroll_delta);
+                    0.0
+                };
+                let pick_position = if input.pressed(Action::Select) && !clicked_to_grab {
+                    if cursor_grabbed {
+                        Some((window_size.width / 2, window_size.height / 2))
+                    } else {
+                        let (x, y) = input.cursor_position();
+                        Some((x.max(0.0) as u32, y.max(0.0) as u32))
+                    }
+                } else {
+                    None
+                };
+                input.end_frame();
+                // Camera paths drive the fly camera and take over from the input.
+                let playing = playback.is_some();
+                if let Some(path) = &mut playback {
+                    use_orbit_camera = false;
+                    if !path.advance(&mut fps_camera, delta_time) {
+                        println!("Playback finished: {}", path.report());
+                        playback = None;
+                        if let Some(video) = video.take() {
+                            finish_video(video);
+                        }
+                    }
+                }
+                let camera: &mut dyn CameraController = if use_orbit_camera {
+                    &mut orbit_camera
+                } else {
+                    &mut fps_camera
+                };
+                if !playing && tiled_capture.is_none() {
+                    camera.move_(&camera_directions, delta_time);
+                    camera.look(mouse_delta, delta_time);
+                    camera.zoom(zoom_delta);
+                }
 
                 let view = camera.view_matrix();
-                let projection = glm::perspective(
-                    window_size.width as f32 / window_size.height as f32,
-                    (45.0f32).to_radians(),
-                    0.1,
-                    100.0,
-                );
+                let tile_projection = tiled_capture
+                    .as_ref()
+                    .map_or_else(glm::Mat4::identity, TiledCapture::tile_projection);
+                let projection = tile_projection * camera.projection_matrix();
+                let view_projection = projection * view;
+
+                let mut model = glm::Mat4::identity();
+                model = glm::translate(&model, &glm::vec3(0.0, -3.0, 0.0));
+                model = glm::scale(&model, &glm::vec3(4.0, 4.0, 4.0));
 
                 unsafe {
-                    gl::ClearColor(0.1, 0.1, 0.1, 1.0);
+                    camera.projection().apply_depth_state();
+                    if let Some((x, y)) = pick_position {
+                        picker.begin(x, y, camera.view_projection());
+                        picker.draw_model(PLANET_ID, &planet, model);
+                        picker.draw_instanced(
+                            ASTEROIDS_ID,
+                            asteroid_field.model(),
+                            asteroid_field.count(),
+                        );
+                        selection = picker.read(x, y);
+                        match selection {
+                            Some(Pick {
+                                object: ASTEROIDS_ID,
+                                instance: Some(instance),
+                                ..
+                            }) => println!("Selected asteroid {}", instance),
+                            Some(Pick { mesh, .. }) => println!("Selected planet mesh {}", mesh),
+                            None => println!("Selection cleared"),
+                        }
+                    }
+
+                    gl::ClearColor(0.01, 0.01, 0.01, 1.0);
                     gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
-                    main_shader.use_program();
-                    main_shader.set_uniform_mat4f("view", view);
-                    main_shader.set_uniform_mat4f("projection", projection);
-
-                    let mut model = glm::Mat4::identity();
-                    model = glm::translate(&model, &glm::vec3(0.0, -3.0, 0.0));
-                    model = glm::scale(&model, &glm::vec3(4.0, 4.0, 4.0));
-                    main_shader.set_uniform_mat4f("model", model);
-                    planet.draw(&main_shader);
-
-                    instanced_shader.use_program();
-                    instanced_shader.set_uniform_mat4f("view", view);
-                    instanced_shader.set_uniform_mat4f("projection", projection);
-                    for mesh in asteroid.meshes.iter() {
-                        mesh.diffuse_textures[0].bind();
-                        gl::BindVertexArray(mesh.vao);
-                        gl::DrawElementsInstanced(
-                            gl::TRIANGLES,
-                            mesh.indices.len() as GLsizei,
-                            gl::UNSIGNED_INT,
-                            0 as *const c_void,
-                            ASTEROID_COUNT as GLsizei,
-                        )
+                    let planet_drawn = match explode_start_time {
+                        Some(start_time) => explode.draw(
+                            &planet,
+                            model,
+                            view,
+                            projection,
+                            time - start_time,
+                            &mut texture_units,
+                        ),
+                        None => {
+                            planet_shader.use_program();
+                            planet_shader.set_uniform_mat4f("view", view);
+                            planet_shader.set_uniform_mat4f("projection", projection);
+                            planet_shader.set_uniform_mat4f("model", model);
+                            texture_units
+                                .bind_uniform(&planet_shader, "detail", &planet_detail, None)
+                                .and_then(|()| planet.draw(&planet_shader, &mut texture_units))
+                        }
+                    };
+                    if let Err(e) = planet_drawn {
+                        eprintln!("Failed to draw the planet: {:#}", e);
+                    }
+                    if show_normals {
+                        if let Err(e) = normal_visualizer.draw(
+                            &planet,
+                            model,
+                            view,
+                            projection,
+                            &mut texture_units,
+                        ) {
+                            eprintln!("Failed to draw normals: {:#}", e);
+                        }
+                    }
+
+                    let asteroid_shader = if asteroid_field.has_texture_array() {
+                        &layered_shader
+                    } else {
+                        &instanced_shader
+                    };
+                    asteroid_shader.use_program();
+                    asteroid_shader.set_uniform_mat4f("viewProjection", view_projection);
+                    if let Err(e) = asteroid_field.draw(asteroid_shader, &mut texture_units) {
+                        eprintln!("Failed to draw the asteroids: {:#}", e);
                     }
+
+                    match selection {
+                        Some(Pick {
+                            object: PLANET_ID, ..
+                        }) => outline.draw(&planet, model, view, projection),
+                        Some(Pick {
+                            object: ASTEROIDS_ID,
+                            instance: Some(instance),
+                            ..
+                        }) => outline.draw(
+                            asteroid_field.model(),
+                            asteroid_field.instance_model_matrix(instance),
+                            view,
+                            projection,
+                        ),
+                        _ => {}
+                    }
+
+                    if take_screenshot {
+                        screenshots.push(Readback::start(0, window_size.width, window_size.height));
+                    }
+                    screenshots.retain(|readback| match readback.poll() {
+                        Ok(Some(image)) => {
+                            screenshot::save(image, SCREENSHOT_DIR.as_ref());
+                            false
+                        }
+                        Ok(None) => true,
+                        Err(e) => {
+                            eprintln!("Failed to take screenshot: {}", e);
+                            false
+                        }
+                    });
+                    if let Some(recorder) = &mut video {
+                        if let Err(e) = recorder.capture() {
+                            eprintln!("Failed to record video frame: {}", e);
+                            finish_video(video.take().unwrap());
+                        }
+                    }
+                    if let Some(capture) = &mut tiled_capture {
+                        if capture.capture_tile(0) {
+                            let image = tiled_capture.take().unwrap().into_image();
+                            screenshot::save(image, SCREENSHOT_DIR.as_ref());
+                        }
+                    }
+                }
+                if let Some(recording) = &mut recording {
+                    recording.push(time, CameraPose::of(&fps_camera));
                 }
                 context.swap_buffers().unwrap();
             }
@@ -274,3 +690,89 @@ fn main() {
         }
     });
 }
+
+fn load_flythrough() -> Option<Playback> {
+    match SplinePath::load(FLYTHROUGH_PATH, glm::vec3(0.0, 1.0, 0.0), FLYTHROUGH_SPEED) {
+        Ok(path) => Some(Playback::new(Box::new(path))),
+        Err(e) => {
+            eprintln!("Failed to load camera path: {}", e);
+            None
+        }
+    }
+}
+
+fn finish_video(video: VideoRecorder) {
+    let frames = video.frames();
+    match video.finish() {
+        Ok(path) => println!("Saved {} video frames to {}", frames, path.display()),
+        Err(e) => eprintln!("Failed to save video: {}", e),
+    }
+}
+
+/// Makes the asteroid texture array layers: the rock texture in a few tints
+/// and a few procedural rock textures of the same size.
+fn asteroid_textures() -> anyhow::Result<Vec<TextureData>> {
+    // Asteroids are opaque, so the layers need no alpha channel.
+    let options = TextureOptions::default()
+        .srgb()
+        .channels(Channels::Rgb)
+        .anisotropy(MAX_ANISOTROPY);
+    let texture = TextureData::decode(ASTEROID_TEXTURE_PATH, &options)?;
+    let mut layers = ASTEROID_TINTS
+        .iter()
+        .map(|&[r, g, b]| texture.tinted(glm::vec3(r, g, b)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (width, height) = texture.dimensions();
+    for (seed, stops) in ASTEROID_NOISE_GRADIENTS.iter().enumerate() {
+        let stops: Vec<_> = stops
+            .iter()
+            .map(|&(t, [r, g, b])| (t, glm::vec4(r, g, b, 1.0)))
+            .collect();
+        let noise = Noise::new(seed as u64);
+        let gradient = Gradient::new(&stops);
+        layers.push(procedural::noise_texture(
+            width, height, &noise, 8, 6, &gradient, &options,
+        )?);
+    }
+    Ok(layers)
+}
+
+/// Makes the grayscale noise the planet shader multiplies its diffuse texture
+/// by, see `shaders/planet.frag`.
+fn planet_detail() -> TextureData {
+    let options = TextureOptions::default()
+        .channels(Channels::R)
+        .anisotropy(MAX_ANISOTROPY);
+    let gradient = Gradient::new(&[
+        (0.0, glm::vec4(0.0, 0.0, 0.0, 1.0)),
+        (1.0, glm::vec4(1.0, 1.0, 1.0, 1.0)),
+    ]);
+    procedural::noise_texture(
+        PLANET_DETAIL_SIZE,
+        PLANET_DETAIL_SIZE,
+        &Noise::new(0),
+        4,
+        5,
+        &gradient,
+        &options,
+    )
+    .expect("Failed to make the planet detail texture")
+}
+
+/// Grabs and hides the cursor for mouse look, or releases and shows it.
+fn set_cursor_grab(window: &Window, grab: bool) {
+    if let Err(e) = window.set_cursor_grab(grab) {
+        let verb = if grab { "grab" } else { "release" };
+        eprintln!("Failed to {} the cursor: {}", verb, e);
+    }
+    window.set_cursor_visible(!grab);
+}
+
+/// Logs loads that could not be queued, which then keep their placeholders.
+fn queued(load: anyhow::Result<LoadId>) -> Option<LoadId> {
+    load.map_err(|e| eprintln!("{:#}", e)).ok()
+}
+
+fn expect_shader<T>(result: Result<T, ShaderError>) -> T {
+    result.unwrap_or_else(|e| panic!("{}", e.pretty()))
+}
@@ -1,38 +1,135 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
 use std::ffi::c_void;
+use std::fs;
 use std::iter;
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::ptr;
 use std::rc::Rc;
+use std::vec;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use memoffset::offset_of;
 use nalgebra_glm as glm;
 
+use crate::asset_manager::{AssetManager, TextureKey};
+use crate::procedural;
+use crate::sampler::Sampler;
 use crate::shader_program::ShaderProgram;
-use crate::texture::Texture;
+use crate::texture::{Channels, Filter, Precision, Texture, TextureData, TextureOptions};
+use crate::texture_units::TextureUnits;
+
+/// Anisotropy requested for model textures, clamped to what the driver allows.
+pub const MAX_ANISOTROPY: f32 = 16.0;
+
+/// Never a canonical path, so the placeholder can share the asset caches with
+/// the models loaded from files.
+const PLACEHOLDER_PATH: &str = "<placeholder>";
+const PLACEHOLDER_RINGS: u32 = 12;
+const PLACEHOLDER_SEGMENTS: u32 = 24;
+const PLACEHOLDER_TEXTURE_SIZE: u32 = 8;
+
+/// Sampler arrays the material textures are bound to, one element per texture.
+const DIFFUSE_SAMPLERS: &str = "material.texture_diffuse";
+const SPECULAR_SAMPLERS: &str = "material.texture_specular";
 
 pub struct Model {
-    pub meshes: Vec<Mesh>,
+    pub meshes: Vec<Rc<Mesh>>,
 }
 
 impl Model {
-    pub unsafe fn load<P>(path: P) -> Result<Self>
+    /// A checkered sphere of radius 1 shown in place of models still loading.
+    pub unsafe fn placeholder(assets: &mut AssetManager) -> Rc<Self> {
+        if let Some(model) = assets.model(PLACEHOLDER_PATH.as_ref()) {
+            return model;
+        }
+        let mut upload = ModelUpload::new(ModelData::placeholder());
+        while !upload.is_done() {
+            upload.step(assets);
+        }
+        upload.finish(assets)
+    }
+
+    /// Lets the placeholder be freed once the models it stood in for have
+    /// replaced it.
+    pub fn unload_placeholder(assets: &mut AssetManager) {
+        assets.unload(PLACEHOLDER_PATH.as_ref());
+    }
+
+    pub unsafe fn draw(
+        &self,
+        shader: &ShaderProgram,
+        texture_units: &mut TextureUnits,
+    ) -> Result<()> {
+        for mesh in self.meshes.iter() {
+            mesh.draw(shader, texture_units, None)?;
+        }
+        Ok(())
+    }
+
+    /// Draws the model sampling every texture with `sampler` instead of the
+    /// samplers of the materials.
+    pub unsafe fn draw_with_sampler(
+        &self,
+        shader: &ShaderProgram,
+        texture_units: &mut TextureUnits,
+        sampler: &Rc<Sampler>,
+    ) -> Result<()> {
+        for mesh in self.meshes.iter() {
+            mesh.draw(shader, texture_units, Some(sampler))?;
+        }
+        Ok(())
+    }
+}
+
+/// A model loaded into memory, ready to be uploaded. Loading it needs no
+/// OpenGL context, so it can happen on any thread.
+pub struct ModelData {
+    /// The canonical path of the model file.
+    path: PathBuf,
+    meshes: Vec<MeshData>,
+    textures: Vec<(TextureKey, TextureData)>,
+}
+
+struct MeshData {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    /// Indices into `ModelData::textures`.
+    diffuse_textures: Vec<usize>,
+    specular_textures: Vec<usize>,
+    /// Options of the sampler to draw with, if any.
+    sampler: Option<TextureOptions>,
+}
+
+impl ModelData {
+    pub fn load<P>(path: P) -> Result<Self>
     where
-        P: AsRef<Path> + std::fmt::Debug,
+        P: AsRef<Path>,
     {
+        let path = fs::canonicalize(path)?;
         let mut texture_loader = TextureLoader::new();
-        let (models, materials) = tobj::load_obj(path.as_ref(), true)?;
+        // Diffuse maps hold colors authored in sRGB. Specular maps hold a
+        // single intensity, which 8 bits are plenty for.
+        let diffuse_options = TextureOptions::default().srgb().anisotropy(MAX_ANISOTROPY);
+        let specular_options = TextureOptions::default()
+            .channels(Channels::R)
+            .precision(Precision::U8)
+            .anisotropy(MAX_ANISOTROPY);
+        let (models, materials) = tobj::load_obj(&path, true)?;
         let meshes = models
             .into_iter()
             .map(|model| {
                 let mesh = model.mesh;
+                if mesh.normals.len() != mesh.positions.len() {
+                    return Err(anyhow!(
+                        "Mesh {} in {} has no normals",
+                        model.name,
+                        path.display()
+                    ));
+                }
                 let positions = mesh.positions.chunks_exact(3);
-                let normals = mesh
-                    .normals
-                    .chunks_exact(3)
-                    .map(|n| Some(n))
-                    .chain(iter::repeat(None))
-                    .take(positions.len());
+                let normals = mesh.normals.chunks_exact(3);
                 let texture_coords = mesh
                     .texcoords
                     .chunks_exact(2)
@@ -43,9 +140,7 @@ impl Model {
                     .zip(normals.zip(texture_coords))
                     .map(|(p, (n, t))| {
                         let position = glm::vec3(p[0], p[1], p[2]);
-                        let normal = n
-                            .map(|n| glm::vec3(n[0], n[1], n[2]))
-                            .expect("No normals in .obj");
+                        let normal = glm::vec3(n[0], n[1], n[2]);
                         let texture_coordinate = t
                             .map(|t| glm::vec2(t[0], t[1]))
                             .unwrap_or(glm::vec2(0.0, 0.0));
@@ -61,58 +156,197 @@ impl Model {
                 let mut specular_textures = vec![];
                 if let Some(id) = mesh.material_id {
                     let material = &materials[id];
-                    let base_path = path.as_ref().parent().unwrap_or("/".as_ref());
+                    let base_path = path.parent().unwrap_or("/".as_ref());
                     if !material.diffuse_texture.is_empty() {
                         let mut path = PathBuf::from(&material.diffuse_texture);
                         if path.is_relative() {
                             path = base_path.join(path);
                         }
-                        diffuse_textures.push(texture_loader.load(path)?);
+                        diffuse_textures.push(texture_loader.load(path, diffuse_options)?);
                     };
                     if !material.specular_texture.is_empty() {
                         let mut path = PathBuf::from(&material.specular_texture);
                         if path.is_relative() {
                             path = base_path.join(path);
                         }
-                        specular_textures.push(texture_loader.load(path)?);
+                        specular_textures.push(texture_loader.load(path, specular_options)?);
                     };
                 }
-                Ok(Mesh::new(
+                Ok(MeshData {
                     vertices,
                     indices,
                     diffuse_textures,
                     specular_textures,
-                ))
+                    sampler: None,
+                })
             })
             .collect::<Result<_>>()?;
-        Ok(Self { meshes })
+        Ok(Self {
+            path,
+            meshes,
+            textures: texture_loader.textures,
+        })
     }
 
-    pub unsafe fn draw(&self, shader: &ShaderProgram) {
-        for mesh in self.meshes.iter() {
-            mesh.draw(shader);
+    fn placeholder() -> Self {
+        let mut vertices = vec![];
+        for ring in 0..=PLACEHOLDER_RINGS {
+            let v = ring as f32 / PLACEHOLDER_RINGS as f32;
+            let polar = v * PI;
+            for segment in 0..=PLACEHOLDER_SEGMENTS {
+                let u = segment as f32 / PLACEHOLDER_SEGMENTS as f32;
+                let azimuth = u * 2.0 * PI;
+                let normal = glm::vec3(
+                    polar.sin() * azimuth.cos(),
+                    polar.cos(),
+                    polar.sin() * azimuth.sin(),
+                );
+                vertices.push(Vertex {
+                    position: normal,
+                    normal,
+                    texture_coordinate: glm::vec2(u, 1.0 - v),
+                });
+            }
+        }
+        let mut indices = vec![];
+        let row = PLACEHOLDER_SEGMENTS + 1;
+        for ring in 0..PLACEHOLDER_RINGS {
+            for segment in 0..PLACEHOLDER_SEGMENTS {
+                let i = ring * row + segment;
+                indices.extend_from_slice(&[i, i + 1, i + row, i + 1, i + row + 1, i + row]);
+            }
+        }
+
+        // The checkers are kept sharp by the sampler rather than the texture,
+        // so the texture can still be sampled smoothly elsewhere.
+        let options = TextureOptions::default().mipmaps(None);
+        // The texture repeats around the sphere but not over the poles.
+        let sampler = options
+            .filter(Filter::Nearest, Filter::Nearest)
+            .wrap(gl::REPEAT, gl::CLAMP_TO_EDGE);
+        let light = glm::vec4(0.63, 0.63, 0.63, 1.0);
+        let dark = glm::vec4(0.38, 0.38, 0.38, 1.0);
+        let size = PLACEHOLDER_TEXTURE_SIZE;
+        let texture = procedural::checkerboard(size, size, light, dark, &options)
+            .expect("Placeholder texture options are valid");
+        let path = PathBuf::from(PLACEHOLDER_PATH);
+        Self {
+            path: path.clone(),
+            meshes: vec![MeshData {
+                vertices,
+                indices,
+                diffuse_textures: vec![0],
+                specular_textures: vec![],
+                sampler: Some(sampler),
+            }],
+            textures: vec![((path, options), texture)],
+        }
+    }
+}
+
+/// Uploads a `ModelData` a texture or mesh at a time, so that large models
+/// can be spread over several frames. Textures and meshes already in the
+/// `AssetManager` are reused rather than uploaded again.
+pub struct ModelUpload {
+    path: PathBuf,
+    textures: vec::IntoIter<(TextureKey, TextureData)>,
+    meshes: iter::Enumerate<vec::IntoIter<MeshData>>,
+    uploaded_textures: Vec<Rc<Texture>>,
+    uploaded_meshes: Vec<Rc<Mesh>>,
+}
+
+impl ModelUpload {
+    pub fn new(data: ModelData) -> Self {
+        Self {
+            path: data.path,
+            textures: data.textures.into_iter(),
+            meshes: data.meshes.into_iter().enumerate(),
+            uploaded_textures: vec![],
+            uploaded_meshes: vec![],
         }
     }
+
+    pub fn is_done(&self) -> bool {
+        self.textures.len() == 0 && self.meshes.len() == 0
+    }
+
+    /// Uploads the next texture or mesh and returns its size in bytes, which
+    /// is 0 if it was already loaded. Textures go first, as meshes refer to
+    /// them.
+    pub unsafe fn step(&mut self, assets: &mut AssetManager) -> usize {
+        if let Some((key, data)) = self.textures.next() {
+            let (texture, size) = match assets.texture(&key) {
+                Some(texture) => (texture, 0),
+                None => {
+                    let size = data.size();
+                    (
+                        assets.insert_texture(key, Texture::upload(&data), size),
+                        size,
+                    )
+                }
+            };
+            self.uploaded_textures.push(texture);
+            return size;
+        }
+        let (index, mesh) = match self.meshes.next() {
+            Some(mesh) => mesh,
+            None => return 0,
+        };
+        let key = (self.path.clone(), index);
+        if let Some(mesh) = assets.mesh(&key) {
+            self.uploaded_meshes.push(mesh);
+            return 0;
+        }
+        let size = mesh.vertices.len() * mem::size_of::<Vertex>()
+            + mesh.indices.len() * mem::size_of::<u32>();
+        let textures = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(|&i| Rc::clone(&self.uploaded_textures[i]))
+                .collect()
+        };
+        let material = Material {
+            diffuse_textures: textures(&mesh.diffuse_textures),
+            specular_textures: textures(&mesh.specular_textures),
+            sampler: mesh.sampler.map(|options| assets.sampler(&options)),
+        };
+        let mesh = Mesh::new(mesh.vertices, mesh.indices, material);
+        self.uploaded_meshes
+            .push(assets.insert_mesh(key, mesh, size));
+        size
+    }
+
+    /// Adds the model to `assets` once `is_done` and returns it.
+    pub fn finish(self, assets: &mut AssetManager) -> Rc<Model> {
+        let model = Model {
+            meshes: self.uploaded_meshes,
+        };
+        assets.insert_model(self.path, model)
+    }
+}
+
+/// The textures a mesh is drawn with and how they are sampled.
+#[derive(Debug)]
+pub struct Material {
+    pub diffuse_textures: Vec<Rc<Texture>>,
+    pub specular_textures: Vec<Rc<Texture>>,
+    /// Samples every texture of the material, overriding the textures' own
+    /// wrapping and filtering. `None` uses those.
+    pub sampler: Option<Rc<Sampler>>,
 }
 
 #[derive(Debug)]
 pub struct Mesh {
     vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
-    pub diffuse_textures: Vec<Rc<Texture>>,
-    specular_textures: Vec<Rc<Texture>>,
+    pub material: Material,
     pub vao: u32,
     vbo: u32,
     ebo: u32,
 }
 
 impl Mesh {
-    unsafe fn new(
-        vertices: Vec<Vertex>,
-        indices: Vec<u32>,
-        diffuse_textures: Vec<Rc<Texture>>,
-        specular_textures: Vec<Rc<Texture>>,
-    ) -> Self {
+    unsafe fn new(vertices: Vec<Vertex>, indices: Vec<u32>, material: Material) -> Self {
         let mut vao = 0;
         let mut vbo = 0;
         let mut ebo = 0;
@@ -168,40 +402,61 @@ impl Mesh {
         Self {
             vertices,
             indices,
-            diffuse_textures,
-            specular_textures,
+            material,
             vao,
             vbo,
             ebo,
         }
     }
 
-    unsafe fn draw(&self, shader: &ShaderProgram) {
-        let mut texture_num = 0;
-        let diffuse_textures = self.diffuse_textures.iter().enumerate();
-        for (diffuse_num, texture) in diffuse_textures {
-            let name = &format!("material.texture_diffuse[{}]", diffuse_num);
-            shader.set_uniform_int(name, texture_num as i32);
-            gl::ActiveTexture(gl::TEXTURE0 + texture_num as u32);
-            texture.bind();
-            texture_num += 1;
-        }
-        let specular_textures = self.specular_textures.iter().enumerate();
-        for (specular_num, texture) in specular_textures {
-            let name = &format!("material.texture_specular[{}]", specular_num);
-            shader.set_uniform_int(name, texture_num as i32);
-            gl::ActiveTexture(gl::TEXTURE0 + texture_num as u32);
-            texture.bind();
-            texture_num += 1;
+    /// Binds the textures of the material to consecutive units, each with
+    /// `sampler` if given and the material's sampler otherwise, and draws the
+    /// mesh.
+    unsafe fn draw(
+        &self,
+        shader: &ShaderProgram,
+        texture_units: &mut TextureUnits,
+        sampler: Option<&Rc<Sampler>>,
+    ) -> Result<()> {
+        self.bind_material(shader, texture_units, sampler)?;
+        self.draw_elements(1);
+        Ok(())
+    }
+
+    /// Binds each texture of the material for the sampler uniform of `shader`
+    /// matching its slot, e.g. `material.texture_diffuse[0]` for the first
+    /// diffuse texture, with `sampler` if given and the material's otherwise.
+    /// Textures the shader has no sampler for are skipped.
+    pub unsafe fn bind_material(
+        &self,
+        shader: &ShaderProgram,
+        texture_units: &mut TextureUnits,
+        sampler: Option<&Rc<Sampler>>,
+    ) -> Result<()> {
+        let sampler = sampler.or(self.material.sampler.as_ref());
+        let slots = [
+            (DIFFUSE_SAMPLERS, &self.material.diffuse_textures),
+            (SPECULAR_SAMPLERS, &self.material.specular_textures),
+        ];
+        for (samplers, textures) in slots.iter() {
+            for (i, texture) in textures.iter().enumerate() {
+                let name = &format!("{}[{}]", samplers, i);
+                texture_units.bind_uniform(shader, name, texture, sampler)?;
+            }
         }
-        gl::ActiveTexture(gl::TEXTURE0);
+        Ok(())
+    }
 
+    /// Draws `instance_count` instances of the mesh with whatever textures and
+    /// shader are currently bound.
+    pub unsafe fn draw_elements(&self, instance_count: usize) {
         gl::BindVertexArray(self.vao);
-        gl::DrawElements(
+        gl::DrawElementsInstanced(
             gl::TRIANGLES,
             self.indices.len() as i32,
             gl::UNSIGNED_INT,
-            0 as *const c_void,
+            ptr::null(),
+            instance_count as i32,
         );
         gl::BindVertexArray(0);
     }
@@ -224,27 +479,46 @@ struct Vertex {
     texture_coordinate: glm::Vec2,
 }
 
+/// Decodes each texture a model uses once, however many meshes refer to it.
 struct TextureLoader {
-    cache: Vec<(PathBuf, Rc<Texture>)>,
+    indices: HashMap<TextureKey, usize>,
+    textures: Vec<(TextureKey, TextureData)>,
 }
 
 impl TextureLoader {
     fn new() -> Self {
-        Self { cache: vec![] }
+        Self {
+            indices: HashMap::new(),
+            textures: vec![],
+        }
     }
 
-    unsafe fn load<P>(&mut self, path: P) -> Result<Rc<Texture>>
+    /// Returns the index of the texture in `textures`.
+    fn load<P>(&mut self, path: P, options: TextureOptions) -> Result<usize>
     where
         P: Into<PathBuf>,
     {
         let path = path.into();
-        match self.cache.iter().find(|(p, _)| *p == path) {
-            Some((_, texture)) => Ok(Rc::clone(&texture)),
-            None => {
-                let texture = Rc::new(Texture::load(&path)?);
-                self.cache.push((path, Rc::clone(&texture)));
-                Ok(texture)
-            }
+        let path = precompressed(&path).unwrap_or(path);
+        let path = fs::canonicalize(&path)
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+        let key = (path, options);
+        if let Some(&index) = self.indices.get(&key) {
+            return Ok(index);
         }
+        let data = TextureData::decode(&key.0, &options)?;
+        self.indices.insert(key.clone(), self.textures.len());
+        self.textures.push((key, data));
+        Ok(self.textures.len() - 1)
     }
 }
+
+/// Finds a block compressed copy of a texture stored next to it under the same
+/// name, e.g. `diffuse.ktx2` for `diffuse.jpg`, which loads faster and takes
+/// less memory than the original.
+fn precompressed(path: &Path) -> Option<PathBuf> {
+    ["ktx2", "ktx", "dds"]
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|path| path.is_file())
+}
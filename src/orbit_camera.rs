@@ -0,0 +1,196 @@
+use nalgebra_glm as glm;
+
+use crate::camera::{
+    self, CameraController, CameraMotion, Projection, SLOW_MULTIPLIER, SPRINT_MULTIPLIER,
+};
+
+/// Degrees of rotation per pixel of mouse motion in turntable mode.
+const ROTATE_SENSITIVITY: f32 = 0.2;
+/// Arcball radii of drag per pixel of mouse motion in arcball mode.
+const ARCBALL_SENSITIVITY: f32 = 0.003;
+/// Fraction of the distance to the target panned per second.
+const PAN_SPEED: f32 = 0.5;
+/// Fraction of the distance to the target dollied per second.
+const DOLLY_SPEED: f32 = 1.0;
+/// Degrees of roll per second in arcball mode.
+const ROLL_SPEED: f32 = 90.0;
+/// Distance factor applied per line of scrolling.
+const SCROLL_DOLLY_FACTOR: f32 = 1.1;
+const MIN_DISTANCE: f32 = 0.5;
+const MAX_DISTANCE: f32 = 500.0;
+/// Keeps turntable mode from flipping over the poles, about 89°.
+const MAX_PITCH_SIN: f32 = 0.9998;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrbitMode {
+    /// Yaw around the world up axis and pitch up to just short of the poles.
+    Turntable,
+    /// Mouse motion drags a virtual sphere around the target, allowing any
+    /// orientation.
+    Arcball,
+}
+
+/// A camera that always looks at a target point from a given distance.
+/// Looking rotates the camera around the target, moving pans the target and
+/// zooming dollies towards it.
+pub struct OrbitCamera {
+    target: glm::Vec3,
+    distance: f32,
+    orientation: glm::Quat,
+    world_up: glm::Vec3,
+    mode: OrbitMode,
+    projection: Projection,
+}
+
+impl OrbitCamera {
+    pub fn new(
+        target: glm::Vec3,
+        distance: f32,
+        world_up: glm::Vec3,
+        projection: Projection,
+    ) -> Self {
+        let front = glm::vec3(0.0, 0.0, -1.0);
+        Self {
+            target,
+            distance: distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+            orientation: camera::look_rotation(&front, &world_up),
+            world_up,
+            mode: OrbitMode::Turntable,
+            projection,
+        }
+    }
+
+    pub fn mode(&self) -> OrbitMode {
+        self.mode
+    }
+
+    /// Switching to turntable mode levels out any roll picked up in arcball
+    /// mode.
+    pub fn set_mode(&mut self, mode: OrbitMode) {
+        if mode == OrbitMode::Turntable {
+            let front = self.front();
+            let pitch_sin = glm::dot(&front, &self.world_up);
+            if pitch_sin.abs() < MAX_PITCH_SIN {
+                self.orientation = camera::look_rotation(&front, &self.world_up);
+            } else {
+                // Looking straight up or down, so level out towards the
+                // camera's up vector instead.
+                let up = self.up();
+                let level_front =
+                    glm::normalize(&(up - self.world_up * glm::dot(&up, &self.world_up)));
+                let level_front = level_front * -pitch_sin.signum();
+                self.orientation = camera::look_rotation(&level_front, &self.world_up);
+            }
+        }
+        self.mode = mode;
+    }
+
+    fn right(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    fn up(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    fn turntable(&mut self, (dx, dy): (f32, f32)) {
+        let yaw = glm::quat_angle_axis((-ROTATE_SENSITIVITY * dx).to_radians(), &self.world_up);
+        self.orientation = glm::quat_normalize(&(yaw * self.orientation));
+        let pitch = glm::quat_angle_axis(
+            (ROTATE_SENSITIVITY * dy).to_radians(),
+            &glm::vec3(1.0, 0.0, 0.0),
+        );
+        let pitched = glm::quat_normalize(&(self.orientation * pitch));
+        let front = glm::quat_rotate_vec3(&pitched, &glm::vec3(0.0, 0.0, -1.0));
+        if glm::dot(&front, &self.world_up).abs() < MAX_PITCH_SIN {
+            self.orientation = pitched;
+        }
+    }
+
+    fn arcball(&mut self, (dx, dy): (f32, f32)) {
+        // Dragging from the centre of the sphere towards the mouse offset
+        // rotates the scene with the drag, so the camera rotates the opposite
+        // way in its own frame.
+        let from = glm::vec3(0.0, 0.0, 1.0);
+        let to = glm::normalize(&glm::vec3(
+            dx * ARCBALL_SENSITIVITY,
+            dy * ARCBALL_SENSITIVITY,
+            1.0,
+        ));
+        let drag = glm::quat_rotation(&from, &to);
+        self.orientation = glm::quat_normalize(&(self.orientation * glm::quat_conjugate(&drag)));
+    }
+}
+
+impl CameraController for OrbitCamera {
+    fn move_(&mut self, directions: &[(CameraMotion, f32)], delta_time: f32) {
+        let mut pan = glm::vec3(0.0, 0.0, 0.0);
+        let mut dolly = 0.0;
+        let mut roll = 0.0;
+        for &(d, weight) in directions {
+            match d {
+                CameraMotion::Forward => dolly -= weight,
+                CameraMotion::Backward => dolly += weight,
+                CameraMotion::Right => pan += self.right() * weight,
+                CameraMotion::Left => pan -= self.right() * weight,
+                CameraMotion::Up => pan += self.up() * weight,
+                CameraMotion::Down => pan -= self.up() * weight,
+                CameraMotion::RollRight => roll += weight,
+                CameraMotion::RollLeft => roll -= weight,
+                CameraMotion::Sprint | CameraMotion::Slow => {}
+            }
+        }
+        let multiplier = camera::speed_multiplier(directions, SPRINT_MULTIPLIER, SLOW_MULTIPLIER);
+        if self.mode == OrbitMode::Arcball && roll != 0.0 {
+            let angle = (ROLL_SPEED * roll * delta_time).to_radians();
+            let rotation = glm::quat_angle_axis(angle, &glm::vec3(0.0, 0.0, -1.0));
+            self.orientation = glm::quat_normalize(&(self.orientation * rotation));
+        }
+        if pan.norm() > 1.0 {
+            pan.normalize_mut();
+        }
+        self.target += PAN_SPEED * multiplier * self.distance * pan * delta_time;
+        self.distance = (self.distance * (1.0 + DOLLY_SPEED * multiplier * dolly * delta_time))
+            .clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
+    fn look(&mut self, mouse_delta: (f32, f32), _delta_time: f32) {
+        if mouse_delta == (0.0, 0.0) {
+            return;
+        }
+        match self.mode {
+            OrbitMode::Turntable => self.turntable(mouse_delta),
+            OrbitMode::Arcball => self.arcball(mouse_delta),
+        }
+    }
+
+    /// Dollies towards the target, and also scales the view volume when
+    /// orthographic since dollying alone would not change the image.
+    fn zoom(&mut self, scroll_delta: f32) {
+        if self.projection.orthographic {
+            self.projection.zoom(scroll_delta);
+        }
+        self.distance = (self.distance * SCROLL_DOLLY_FACTOR.powf(scroll_delta))
+            .clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
+    fn view_matrix(&self) -> glm::Mat4 {
+        glm::look_at(&self.position(), &self.target, &self.up())
+    }
+
+    fn position(&self) -> glm::Vec3 {
+        self.target - self.front() * self.distance
+    }
+
+    fn front(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 0.0, -1.0))
+    }
+
+    fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    fn projection_mut(&mut self) -> &mut Projection {
+        &mut self.projection
+    }
+}
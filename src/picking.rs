@@ -0,0 +1,190 @@
+use gl::types::*;
+use nalgebra_glm as glm;
+
+use crate::model::Model;
+use crate::shader_error::ShaderError;
+use crate::shader_program::ShaderProgram;
+
+const VERTEX_SHADER: &str = include_str!("shaders/pick.vert");
+const INSTANCED_VERTEX_SHADER: &str = include_str!("shaders/pick_instanced.vert");
+const FRAGMENT_SHADER: &str = include_str!("shaders/pick.frag");
+
+/// Instance value written for draws that are not instanced.
+const NO_INSTANCE: u32 = u32::MAX;
+
+/// What was drawn at a picked pixel. `object` is the id passed when drawing,
+/// `mesh` the index into the model's meshes and `instance` the instance index
+/// for instanced draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pick {
+    pub object: u32,
+    pub mesh: usize,
+    pub instance: Option<usize>,
+}
+
+/// Picks objects by rendering their ids into an integer framebuffer and reading
+/// back the pixel under the cursor.
+///
+/// A pick is done with `begin`, any number of draws and then `read`. Object id
+/// 0 is reserved for the background.
+pub struct Picker {
+    framebuffer: u32,
+    id_renderbuffer: u32,
+    depth_renderbuffer: u32,
+    width: u32,
+    height: u32,
+    shader: ShaderProgram,
+    instanced_shader: ShaderProgram,
+    view_projection: glm::Mat4,
+}
+
+impl Picker {
+    pub unsafe fn new(width: u32, height: u32) -> Result<Self, ShaderError> {
+        let shader = ShaderProgram::new(VERTEX_SHADER, FRAGMENT_SHADER, None)?;
+        let instanced_shader = ShaderProgram::new(INSTANCED_VERTEX_SHADER, FRAGMENT_SHADER, None)?;
+        let mut framebuffer = 0;
+        let mut renderbuffers = [0; 2];
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::GenRenderbuffers(2, renderbuffers.as_mut_ptr());
+        let mut picker = Self {
+            framebuffer,
+            id_renderbuffer: renderbuffers[0],
+            depth_renderbuffer: renderbuffers[1],
+            width: 0,
+            height: 0,
+            shader,
+            instanced_shader,
+            view_projection: glm::Mat4::identity(),
+        };
+        picker.resize(width, height);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, picker.framebuffer);
+        gl::FramebufferRenderbuffer(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::RENDERBUFFER,
+            picker.id_renderbuffer,
+        );
+        gl::FramebufferRenderbuffer(
+            gl::FRAMEBUFFER,
+            gl::DEPTH_ATTACHMENT,
+            gl::RENDERBUFFER,
+            picker.depth_renderbuffer,
+        );
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        assert_eq!(
+            status,
+            gl::FRAMEBUFFER_COMPLETE,
+            "Picking framebuffer is incomplete"
+        );
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        Ok(picker)
+    }
+
+    /// Matches the framebuffer to the window size.
+    pub unsafe fn resize(&mut self, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.id_renderbuffer);
+        gl::RenderbufferStorage(
+            gl::RENDERBUFFER,
+            gl::RGBA32UI,
+            self.width as GLsizei,
+            self.height as GLsizei,
+        );
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth_renderbuffer);
+        gl::RenderbufferStorage(
+            gl::RENDERBUFFER,
+            gl::DEPTH_COMPONENT32F,
+            self.width as GLsizei,
+            self.height as GLsizei,
+        );
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+    }
+
+    /// Starts a pick of pixel (`x`, `y`), measured from the top left corner.
+    /// Only that pixel is cleared and drawn to. The depth test is left as
+    /// configured by the caller.
+    pub unsafe fn begin(&mut self, x: u32, y: u32, view_projection: glm::Mat4) {
+        self.view_projection = view_projection;
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::Enable(gl::SCISSOR_TEST);
+        let (x, y) = self.gl_pixel(x, y);
+        gl::Scissor(x, y, 1, 1);
+        let background = [0u32; 4];
+        gl::ClearBufferuiv(gl::COLOR, 0, background.as_ptr());
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+    }
+
+    pub unsafe fn draw_model(&self, object: u32, model: &Model, model_matrix: glm::Mat4) {
+        self.shader.use_program();
+        self.shader
+            .set_uniform_mat4f("viewProjection", self.view_projection);
+        self.shader.set_uniform_mat4f("model", model_matrix);
+        self.shader.set_uniform_uint("object", object);
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            self.shader.set_uniform_uint("mesh", i as u32);
+            mesh.draw_elements(1);
+        }
+    }
+
+    /// Draws `count` instances of a model whose meshes have per-instance model
+    /// matrices set up at attribute location 3, like `AsteroidField`'s.
+    pub unsafe fn draw_instanced(&self, object: u32, model: &Model, count: usize) {
+        self.instanced_shader.use_program();
+        self.instanced_shader
+            .set_uniform_mat4f("viewProjection", self.view_projection);
+        self.instanced_shader.set_uniform_uint("object", object);
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            self.instanced_shader.set_uniform_uint("mesh", i as u32);
+            mesh.draw_elements(count);
+        }
+    }
+
+    /// Finishes the pick started by `begin`, returning what was drawn at the
+    /// pixel, if anything, and binding the default framebuffer again.
+    pub unsafe fn read(&self, x: u32, y: u32) -> Option<Pick> {
+        let (x, y) = self.gl_pixel(x, y);
+        let mut id = [0u32; 4];
+        gl::ReadPixels(
+            x,
+            y,
+            1,
+            1,
+            gl::RGBA_INTEGER,
+            gl::UNSIGNED_INT,
+            id.as_mut_ptr() as *mut _,
+        );
+        gl::Disable(gl::SCISSOR_TEST);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        match id {
+            [0, ..] => None,
+            [object, mesh, instance, _] => Some(Pick {
+                object,
+                mesh: mesh as usize,
+                instance: if instance == NO_INSTANCE {
+                    None
+                } else {
+                    Some(instance as usize)
+                },
+            }),
+        }
+    }
+
+    /// Converts a pixel measured from the top left into GL window coordinates
+    /// measured from the bottom left, clamped to the framebuffer.
+    fn gl_pixel(&self, x: u32, y: u32) -> (GLint, GLint) {
+        let x = x.min(self.width - 1);
+        let y = self.height - 1 - y.min(self.height - 1);
+        (x as GLint, y as GLint)
+    }
+}
+
+impl Drop for Picker {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            let renderbuffers = [self.id_renderbuffer, self.depth_renderbuffer];
+            gl::DeleteRenderbuffers(2, renderbuffers.as_ptr());
+        }
+    }
+}
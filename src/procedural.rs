@@ -0,0 +1,350 @@
+use anyhow::Result;
+use nalgebra_glm as glm;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::texture::{TextureData, TextureOptions};
+
+/// Seeded gradient noise.
+pub struct Noise {
+    permutation: [u8; 512],
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut values: Vec<u8> = (0..=255).collect();
+        values.shuffle(&mut StdRng::seed_from_u64(seed));
+        let mut permutation = [0; 512];
+        for (i, value) in permutation.iter_mut().enumerate() {
+            *value = values[i % 256];
+        }
+        Self { permutation }
+    }
+
+    /// Perlin noise in about [-1, 1], repeating every `period` units along
+    /// both axes. The period is at most 256.
+    pub fn perlin(&self, x: f32, y: f32, period: u32) -> f32 {
+        let period = period.clamp(1, 256) as i32;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let xi = (x0 as i32).rem_euclid(period) as usize;
+        let yi = (y0 as i32).rem_euclid(period) as usize;
+        let xj = (xi + 1) % period as usize;
+        let yj = (yi + 1) % period as usize;
+
+        let n00 = gradient(self.hash(xi, yi), fx, fy);
+        let n10 = gradient(self.hash(xj, yi), fx - 1.0, fy);
+        let n01 = gradient(self.hash(xi, yj), fx, fy - 1.0);
+        let n11 = gradient(self.hash(xj, yj), fx - 1.0, fy - 1.0);
+        let (u, v) = (fade(fx), fade(fy));
+        lerp(lerp(n00, n10, u), lerp(n01, n11, u), v)
+    }
+
+    /// Simplex noise in about [-1, 1]. It is cheaper than Perlin noise and has
+    /// no grid artifacts, but does not tile.
+    pub fn simplex(&self, x: f32, y: f32) -> f32 {
+        let f2 = 0.5 * (3.0f32.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0f32.sqrt()) / 6.0;
+        let s = (x + y) * f2;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * g2;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let corners = [
+            (0, 0, x0, y0),
+            (i1, j1, x0 - i1 as f32 + g2, y0 - j1 as f32 + g2),
+            (1, 1, x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2),
+        ];
+        let (i, j) = ((i as i32 & 255) as usize, (j as i32 & 255) as usize);
+        let sum: f32 = corners
+            .iter()
+            .map(|&(di, dj, x, y)| {
+                let t = 0.5 - x * x - y * y;
+                if t < 0.0 {
+                    0.0
+                } else {
+                    t.powi(4) * gradient(self.hash(i + di, j + dj), x, y)
+                }
+            })
+            .sum();
+        70.0 * sum
+    }
+
+    /// Fractal Brownian motion: `octaves` layers of Perlin noise, each at
+    /// twice the frequency and `gain` times the amplitude of the last,
+    /// starting at `frequency` cells across the unit square. Tiles over the
+    /// unit square and stays in about [-1, 1].
+    pub fn fbm(&self, x: f32, y: f32, frequency: u32, octaves: u32, gain: f32) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total_amplitude = 0.0;
+        let mut frequency = frequency;
+        for _ in 0..octaves {
+            let f = frequency as f32;
+            sum += amplitude * self.perlin(x * f, y * f, frequency);
+            total_amplitude += amplitude;
+            amplitude *= gain;
+            frequency = (frequency * 2).min(256);
+        }
+        sum / total_amplitude.max(f32::EPSILON)
+    }
+
+    fn hash(&self, x: usize, y: usize) -> u8 {
+        self.permutation[self.permutation[x & 255] as usize + (y & 255)]
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Dot product of the offset with one of eight gradient directions.
+fn gradient(hash: u8, x: f32, y: f32) -> f32 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+/// Colors interpolated between stops in [0, 1].
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    stops: Vec<(f32, glm::Vec4)>,
+}
+
+impl Gradient {
+    pub fn new(stops: &[(f32, glm::Vec4)]) -> Self {
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops }
+    }
+
+    /// The color at `t`, clamped to the first and last stops.
+    pub fn color(&self, t: f32) -> glm::Vec4 {
+        let next = self.stops.iter().position(|&(position, _)| position > t);
+        match next {
+            None => self.stops.last().map_or(glm::Vec4::zeros(), |stop| stop.1),
+            Some(0) => self.stops[0].1,
+            Some(i) => {
+                let (start, from) = self.stops[i - 1];
+                let (end, to) = self.stops[i];
+                glm::lerp(&from, &to, (t - start) / (end - start))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientShape {
+    /// From left to right.
+    Linear,
+    /// From the center to the edges.
+    Radial,
+}
+
+impl GradientShape {
+    /// Where along the gradient the texture coordinates `(u, v)` are, from 0
+    /// to 1.
+    fn position(self, u: f32, v: f32) -> f32 {
+        match self {
+            GradientShape::Linear => u,
+            GradientShape::Radial => glm::distance(&glm::vec2(u, v), &glm::vec2(0.5, 0.5)) * 2.0,
+        }
+    }
+}
+
+/// A `size` by `size` checkerboard of `squares` by `squares` squares.
+pub fn checkerboard(
+    size: u32,
+    squares: u32,
+    color_a: glm::Vec4,
+    color_b: glm::Vec4,
+    options: &TextureOptions,
+) -> Result<TextureData> {
+    generate(size, size, options, |u, v| {
+        let (x, y) = ((u * squares as f32) as u32, (v * squares as f32) as u32);
+        if (x + y).is_multiple_of(2) {
+            color_a
+        } else {
+            color_b
+        }
+    })
+}
+
+/// A texture colored by `gradient` along the given shape.
+pub fn gradient_texture(
+    width: u32,
+    height: u32,
+    gradient: &Gradient,
+    shape: GradientShape,
+    options: &TextureOptions,
+) -> Result<TextureData> {
+    generate(width, height, options, |u, v| {
+        gradient.color(shape.position(u, v))
+    })
+}
+
+/// Tiling fBm noise colored by `gradient`, see `Noise::fbm`.
+pub fn noise_texture(
+    width: u32,
+    height: u32,
+    noise: &Noise,
+    frequency: u32,
+    octaves: u32,
+    gradient: &Gradient,
+    options: &TextureOptions,
+) -> Result<TextureData> {
+    generate(width, height, options, |u, v| {
+        let value = noise.fbm(u, v, frequency, octaves, 0.5);
+        gradient.color(value * 0.5 + 0.5)
+    })
+}
+
+/// A tiling tangent space normal map of an fBm height field, see
+/// `Noise::fbm`. `strength` scales the height differences between texels.
+/// Normal maps hold data, so the options should not be sRGB.
+pub fn normal_map(
+    size: u32,
+    noise: &Noise,
+    frequency: u32,
+    octaves: u32,
+    strength: f32,
+    options: &TextureOptions,
+) -> Result<TextureData> {
+    let texel = 1.0 / size as f32;
+    let height = |u: f32, v: f32| noise.fbm(u, v, frequency, octaves, 0.5);
+    generate(size, size, options, |u, v| {
+        let normal = height_normal(&height, u, v, texel, strength);
+        let encoded = normal * 0.5 + glm::vec3(0.5, 0.5, 0.5);
+        glm::vec4(encoded.x, encoded.y, encoded.z, 1.0)
+    })
+}
+
+/// The normal of the height field `height` at `(u, v)`, from the central
+/// differences `texel` away.
+fn height_normal<F>(height: &F, u: f32, v: f32, texel: f32, strength: f32) -> glm::Vec3
+where
+    F: Fn(f32, f32) -> f32,
+{
+    let dx = height(u + texel, v) - height(u - texel, v);
+    let dy = height(u, v + texel) - height(u, v - texel);
+    glm::normalize(&glm::vec3(-dx * strength, -dy * strength, 1.0))
+}
+
+/// Builds a texture from the color at the center of each pixel, given as
+/// texture coordinates.
+fn generate<F>(width: u32, height: u32, options: &TextureOptions, color: F) -> Result<TextureData>
+where
+    F: Fn(f32, f32) -> glm::Vec4,
+{
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let u = (x as f32 + 0.5) / width as f32;
+            let v = (y as f32 + 0.5) / height as f32;
+            let color = color(u, v);
+            pixels.extend(
+                color
+                    .iter()
+                    .map(|&c| (c.clamp(0.0, 1.0) * 255.0).round() as u8),
+            );
+        }
+    }
+    TextureData::from_rgba8(width, height, pixels, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample points spread over a few noise cells, off the lattice.
+    fn grid() -> impl Iterator<Item = (f32, f32)> {
+        (0..32).flat_map(|y| (0..32).map(move |x| (x as f32 * 0.37 + 0.1, y as f32 * 0.29 + 0.2)))
+    }
+
+    #[test]
+    fn noise_is_deterministic_for_a_seed() {
+        let (a, b, other) = (Noise::new(7), Noise::new(7), Noise::new(8));
+        let values = |noise: &Noise| -> Vec<_> {
+            grid()
+                .map(|(x, y)| (noise.perlin(x, y, 16), noise.simplex(x, y)))
+                .collect()
+        };
+        assert_eq!(values(&a), values(&b));
+        assert_ne!(values(&a), values(&other));
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let noise = Noise::new(3);
+        for (x, y) in grid() {
+            for value in [
+                noise.perlin(x, y, 16),
+                noise.simplex(x, y),
+                noise.fbm(x / 12.0, y / 12.0, 4, 5, 0.5),
+            ]
+            .iter()
+            {
+                assert!(value.abs() <= 1.0, "{} at ({}, {})", value, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn perlin_noise_repeats_every_period() {
+        let noise = Noise::new(1);
+        for (x, y) in grid() {
+            let value = noise.perlin(x, y, 4);
+            assert!((value - noise.perlin(x + 4.0, y, 4)).abs() < 1e-5);
+            assert!((value - noise.perlin(x, y - 8.0, 4)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn perlin_noise_is_zero_on_the_lattice() {
+        let noise = Noise::new(1);
+        assert_eq!(noise.perlin(3.0, 5.0, 16), 0.0);
+    }
+
+    #[test]
+    fn gradients_interpolate_and_clamp() {
+        let black = glm::vec4(0.0, 0.0, 0.0, 1.0);
+        let white = glm::vec4(1.0, 1.0, 1.0, 1.0);
+        let gradient = Gradient::new(&[(0.75, white), (0.25, black)]);
+        assert_eq!(gradient.color(0.0), black);
+        assert_eq!(gradient.color(0.5), glm::vec4(0.5, 0.5, 0.5, 1.0));
+        assert_eq!(gradient.color(1.0), white);
+    }
+
+    #[test]
+    fn gradient_shapes_map_coordinates_to_positions() {
+        assert_eq!(GradientShape::Linear.position(0.25, 0.9), 0.25);
+        assert_eq!(GradientShape::Radial.position(0.5, 0.5), 0.0);
+        assert!((GradientShape::Radial.position(1.0, 0.5) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn flat_heights_give_upward_normals() {
+        let normal = height_normal(&|_, _| 0.4, 0.3, 0.6, 1.0 / 64.0, 8.0);
+        assert_eq!(normal, glm::vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn slopes_tilt_normals_downhill() {
+        let normal = height_normal(&|u, _| u, 0.5, 0.5, 0.25, 1.0);
+        assert!(normal.x < 0.0);
+        assert_eq!(normal.y, 0.0);
+        assert!((glm::length(&normal) - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::texture::TextureOptions;
+
+/// A GL sampler object. Bound to a texture unit, it overrides the wrapping and
+/// filtering of whatever texture is bound there, so the same texture can be
+/// sampled differently from one pass to the next.
+///
+/// Mipmap filters need textures that have mipmaps; textures without them are
+/// incomplete and sample as black under such a sampler.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Sampler {
+    id: u32,
+}
+
+impl Sampler {
+    /// Samples with the wrapping, filtering and anisotropy of `options`.
+    pub unsafe fn new(options: &TextureOptions) -> Self {
+        let mut id = 0;
+        gl::GenSamplers(1, &mut id);
+        options.apply_to_sampler(id);
+        Self { id }
+    }
+
+    pub unsafe fn bind(&self, unit: u32) {
+        gl::BindSampler(unit, self.id);
+    }
+
+    /// Goes back to sampling the texture on `unit` with its own parameters.
+    pub unsafe fn unbind(unit: u32) {
+        gl::BindSampler(unit, 0);
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteSamplers(1, &self.id);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::ffi::c_void;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use gl::types::*;
+use image::{imageops, RgbImage};
+use nalgebra_glm as glm;
+
+/// Reads a framebuffer back into a pixel buffer object, so that the copy
+/// happens without stalling the render thread, and hands the pixels out once
+/// the GPU has finished it.
+pub struct Readback {
+    pbo: u32,
+    fence: GLsync,
+    width: u32,
+    height: u32,
+}
+
+impl Readback {
+    /// Starts copying the color buffer of `framebuffer`, 0 being the default
+    /// framebuffer, after the draws issued so far.
+    pub unsafe fn start(framebuffer: u32, width: u32, height: u32) -> Self {
+        let size = (width * height * 3) as GLsizeiptr;
+        let mut pbo = 0;
+        gl::GenBuffers(1, &mut pbo);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pbo);
+        gl::BufferData(gl::PIXEL_PACK_BUFFER, size, ptr::null(), gl::STREAM_READ);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as GLsizei,
+            height as GLsizei,
+            gl::RGB,
+            gl::UNSIGNED_BYTE,
+            ptr::null_mut(),
+        );
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        Self {
+            pbo,
+            fence,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the image, top row first, once the copy has finished, or an
+    /// error if the pixel buffer cannot be read.
+    pub unsafe fn poll(&self) -> Result<Option<RgbImage>> {
+        let status = gl::ClientWaitSync(self.fence, gl::SYNC_FLUSH_COMMANDS_BIT, 0);
+        if status != gl::ALREADY_SIGNALED && status != gl::CONDITION_SATISFIED {
+            return Ok(None);
+        }
+        let size = (self.width * self.height * 3) as usize;
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.pbo);
+        let data = gl::MapBufferRange(
+            gl::PIXEL_PACK_BUFFER,
+            0,
+            size as GLsizeiptr,
+            gl::MAP_READ_BIT,
+        ) as *const u8;
+        if data.is_null() {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            return Err(anyhow!("Failed to map the pixel buffer"));
+        }
+        let pixels = std::slice::from_raw_parts(data, size).to_vec();
+        gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        let image = RgbImage::from_raw(self.width, self.height, pixels)
+            .ok_or_else(|| anyhow!("Pixel buffer does not match the image size"))?;
+        Ok(Some(imageops::flip_vertical(&image)))
+    }
+}
+
+impl Drop for Readback {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteSync(self.fence);
+            gl::DeleteBuffers(1, &self.pbo);
+        }
+    }
+}
+
+/// Renders an image at `scale` times the window size by drawing the scene
+/// `scale` by `scale` times, each time zoomed in on one tile, and reading the
+/// tiles back.
+pub struct TiledCapture {
+    scale: u32,
+    tile: u32,
+    tile_width: u32,
+    tile_height: u32,
+    image: RgbImage,
+}
+
+impl TiledCapture {
+    pub fn new(scale: u32, tile_width: u32, tile_height: u32) -> Self {
+        Self {
+            scale,
+            tile: 0,
+            tile_width,
+            tile_height,
+            image: RgbImage::new(tile_width * scale, tile_height * scale),
+        }
+    }
+
+    /// Matrix applied on top of the projection so that the current tile
+    /// fills the viewport.
+    pub fn tile_projection(&self) -> glm::Mat4 {
+        let (column, row) = (self.tile % self.scale, self.tile / self.scale);
+        let scale = self.scale as f32;
+        // Center of the tile in normalized device coordinates.
+        let x = -1.0 + (2 * column + 1) as f32 / scale;
+        let y = -1.0 + (2 * row + 1) as f32 / scale;
+        glm::translation(&glm::vec3(-x * scale, -y * scale, 0.0))
+            * glm::scaling(&glm::vec3(scale, scale, 1.0))
+    }
+
+    /// Reads the current tile back from `framebuffer` and moves on to the
+    /// next one. Returns `true` once every tile has been captured.
+    pub unsafe fn capture_tile(&mut self, framebuffer: u32) -> bool {
+        let (width, height) = (self.tile_width, self.tile_height);
+        let tile = read_pixels(framebuffer, width, height);
+        let (column, row) = (self.tile % self.scale, self.tile / self.scale);
+        // Tile rows count up from the bottom like GL rows.
+        let top = (self.scale - 1 - row) * height;
+        imageops::replace(&mut self.image, &tile, column * width, top);
+        self.tile += 1;
+        self.tile == self.scale * self.scale
+    }
+
+    pub fn into_image(self) -> RgbImage {
+        self.image
+    }
+}
+
+/// Reads the color buffer of `framebuffer`, waiting for the draws issued so
+/// far to finish. Returns the image top row first.
+pub unsafe fn read_pixels(framebuffer: u32, width: u32, height: u32) -> RgbImage {
+    let mut pixels = vec![0u8; (width * height * 3) as usize];
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+        0,
+        0,
+        width as GLsizei,
+        height as GLsizei,
+        gl::RGB,
+        gl::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as *mut c_void,
+    );
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+    let image = RgbImage::from_raw(width, height, pixels).expect("Pixels fill the image");
+    imageops::flip_vertical(&image)
+}
+
+/// Saves `image` as a timestamped PNG in `dir` on a background thread, as
+/// encoding large images takes a while.
+pub fn save(image: RgbImage, dir: &Path) {
+    let path = dir.join(format!("screenshot_{}.png", timestamp()));
+    thread::spawn(move || match write_png(&image, &path) {
+        Ok(()) => println!("Saved screenshot to {}", path.display()),
+        Err(e) => eprintln!("Failed to save screenshot {}: {}", path.display(), e),
+    });
+}
+
+pub fn write_png(image: &RgbImage, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    image.save(path)?;
+    Ok(())
+}
+
+/// The current UTC time as `YYYY-MM-DD_hh-mm-ss-mmm`, which sorts by time.
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}-{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian date, following
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_the_epoch() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn converts_leap_days() {
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+    }
+}
@@ -0,0 +1,326 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::shader_program::ShaderStage;
+
+/// Number of source lines shown before and after an offending line.
+const CONTEXT_LINES: usize = 2;
+
+#[derive(Debug)]
+pub enum ShaderError {
+    /// The source contained an interior nul byte and could not be passed to GL.
+    InvalidSource {
+        stage: ShaderStage,
+        position: usize,
+    },
+    Compile {
+        stage: ShaderStage,
+        source: String,
+        diagnostics: Vec<Diagnostic>,
+    },
+    Link {
+        diagnostics: Vec<Diagnostic>,
+    },
+}
+
+impl ShaderError {
+    pub fn compile(stage: ShaderStage, source: &str, log: &str) -> Self {
+        Self::Compile {
+            stage,
+            source: source.to_owned(),
+            diagnostics: parse_info_log(log),
+        }
+    }
+
+    pub fn link(log: &str) -> Self {
+        Self::Link {
+            diagnostics: parse_info_log(log),
+        }
+    }
+
+    /// Formats the error with every diagnostic followed by the source lines it
+    /// refers to.
+    pub fn pretty(&self) -> String {
+        let mut output = format!("{}\n", self);
+        match self {
+            Self::InvalidSource { .. } => {}
+            Self::Compile {
+                stage,
+                source,
+                diagnostics,
+            } => {
+                let lines: Vec<_> = source.lines().collect();
+                for diagnostic in diagnostics {
+                    output += &format!("{}\n", diagnostic);
+                    if let Some(line) = diagnostic.line {
+                        output += &format!("  --> {} shader:{}\n", stage, line);
+                        output += &source_context(&lines, line as usize);
+                    }
+                }
+            }
+            Self::Link { diagnostics } => {
+                for diagnostic in diagnostics {
+                    output += &format!("{}\n", diagnostic);
+                }
+            }
+        }
+        output
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidSource { stage, position } => write!(
+                f,
+                "{} shader source contains a nul byte at offset {}",
+                stage, position
+            ),
+            Self::Compile {
+                stage, diagnostics, ..
+            } => write!(
+                f,
+                "{} shader failed to compile with {} diagnostic(s)",
+                stage,
+                diagnostics.len()
+            ),
+            Self::Link { diagnostics } => write!(
+                f,
+                "shader program failed to link with {} diagnostic(s)",
+                diagnostics.len()
+            ),
+        }
+    }
+}
+
+impl Error for ShaderError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// A log line without a recognizable severity.
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A single line of a shader info log. Lines that cannot be parsed are kept as
+/// notes so that no part of the log is lost. `file` is the GLSL source string
+/// number, which is always 0 for programs built by `ShaderProgram`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<u32>,
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+fn parse_info_log(log: &str) -> Vec<Diagnostic> {
+    log.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(parse_diagnostic)
+        .collect()
+}
+
+/// Parses the info log line formats used by the common drivers:
+///
+/// - Mesa: `0:12(5): error: message`
+/// - NVIDIA: `0(12) : error C1008: message`
+/// - AMD, Intel and Apple: `ERROR: 0:12: message`
+fn parse_diagnostic(text: &str) -> Diagnostic {
+    let (leading_severity, rest) = split_severity(text);
+    let (file, line, rest) = match split_location(rest) {
+        Some(location) => location,
+        None => {
+            return Diagnostic {
+                severity: leading_severity.unwrap_or(Severity::Note),
+                file: None,
+                line: None,
+                message: rest.trim().to_owned(),
+            }
+        }
+    };
+    let (trailing_severity, message) = split_severity(rest);
+    Diagnostic {
+        severity: leading_severity
+            .or(trailing_severity)
+            .unwrap_or(Severity::Note),
+        file: Some(file),
+        line: Some(line),
+        message: message.trim().to_owned(),
+    }
+}
+
+/// Splits off a leading `error:`/`warning:` label, allowing a vendor error
+/// code between the label and the colon.
+fn split_severity(text: &str) -> (Option<Severity>, &str) {
+    let text = text.trim_start();
+    let colon = match text.find(':') {
+        Some(colon) => colon,
+        None => return (None, text),
+    };
+    let mut words = text[..colon].split_whitespace();
+    let severity = match words.next().map(str::to_ascii_lowercase).as_deref() {
+        Some("error") => Severity::Error,
+        Some("warning") => Severity::Warning,
+        _ => return (None, text),
+    };
+    if words.count() > 1 {
+        return (None, text);
+    }
+    (Some(severity), &text[colon + 1..])
+}
+
+/// Splits off a `file:line:`, `file:line(column):` or `file(line) :` location.
+fn split_location(text: &str) -> Option<(u32, u32, &str)> {
+    let text = text.trim_start();
+    let (file, rest) = split_number(text)?;
+    let (line, rest) = if let Some(rest) = rest.strip_prefix(':') {
+        let (line, mut rest) = split_number(rest)?;
+        if let Some(column) = rest.strip_prefix('(') {
+            let (_, column_rest) = split_number(column)?;
+            rest = column_rest.strip_prefix(')')?;
+        }
+        (line, rest)
+    } else {
+        let (line, rest) = split_number(rest.strip_prefix('(')?)?;
+        (line, rest.strip_prefix(')')?)
+    };
+    let rest = rest.trim_start().strip_prefix(':')?;
+    Some((file, line, rest))
+}
+
+fn split_number(text: &str) -> Option<(u32, &str)> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let number = text[..end].parse().ok()?;
+    Some((number, &text[end..]))
+}
+
+fn source_context(lines: &[&str], line: usize) -> String {
+    if line == 0 || line > lines.len() {
+        return String::new();
+    }
+    let first = line.saturating_sub(CONTEXT_LINES).max(1);
+    let last = (line + CONTEXT_LINES).min(lines.len());
+    let width = last.to_string().len();
+    let mut output = String::new();
+    for number in first..=last {
+        let marker = if number == line { '>' } else { ' ' };
+        output += &format!(
+            "{} {:>width$} | {}\n",
+            marker,
+            number,
+            lines[number - 1],
+            width = width
+        );
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(severity: Severity, line: Option<u32>, message: &str) -> Diagnostic {
+        Diagnostic {
+            severity,
+            file: line.map(|_| 0),
+            line,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parses_mesa_lines() {
+        assert_eq!(
+            parse_diagnostic("0:12(5): error: `foo' undeclared"),
+            diagnostic(Severity::Error, Some(12), "`foo' undeclared")
+        );
+        assert_eq!(
+            parse_diagnostic("0:3(10): warning: unused variable"),
+            diagnostic(Severity::Warning, Some(3), "unused variable")
+        );
+    }
+
+    #[test]
+    fn parses_nvidia_lines() {
+        assert_eq!(
+            parse_diagnostic("0(12) : error C1008: undefined variable \"foo\""),
+            diagnostic(Severity::Error, Some(12), "undefined variable \"foo\"")
+        );
+    }
+
+    #[test]
+    fn parses_amd_lines() {
+        assert_eq!(
+            parse_diagnostic("ERROR: 0:7: 'foo' : undeclared identifier"),
+            diagnostic(Severity::Error, Some(7), "'foo' : undeclared identifier")
+        );
+        assert_eq!(
+            parse_diagnostic("WARNING: 0:2: extension not supported"),
+            diagnostic(Severity::Warning, Some(2), "extension not supported")
+        );
+    }
+
+    #[test]
+    fn keeps_unrecognized_lines_as_notes() {
+        assert_eq!(
+            parse_diagnostic("Vertex info"),
+            diagnostic(Severity::Note, None, "Vertex info")
+        );
+        assert_eq!(
+            parse_diagnostic("error: linking with uncompiled shader"),
+            diagnostic(Severity::Error, None, "linking with uncompiled shader")
+        );
+        // A location without a closing parenthesis is not a location.
+        assert_eq!(
+            parse_diagnostic("0(12 : error: oops"),
+            diagnostic(Severity::Note, None, "0(12 : error: oops")
+        );
+        // Too many words before the colon to be a severity with a code.
+        assert_eq!(
+            parse_diagnostic("0:4(1): error in the code: oops"),
+            diagnostic(Severity::Note, Some(4), "error in the code: oops")
+        );
+    }
+
+    #[test]
+    fn splits_logs_into_lines_and_skips_blank_ones() {
+        let log = "0:1(1): error: first\n\n   \r\n0:2(1): warning: second\n";
+        let diagnostics = parse_info_log(log);
+        assert_eq!(
+            diagnostics,
+            vec![
+                diagnostic(Severity::Error, Some(1), "first"),
+                diagnostic(Severity::Warning, Some(2), "second"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_source_around_the_offending_line() {
+        let lines = ["a", "b", "c", "d", "e", "f"];
+        assert_eq!(source_context(&lines, 1), "> 1 | a\n  2 | b\n  3 | c\n");
+        assert_eq!(source_context(&lines, 0), "");
+        assert_eq!(source_context(&lines, 7), "");
+    }
+}
@@ -1,13 +1,55 @@
-use std::ffi::CString;
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr, CString};
+use std::fmt;
+use std::fs;
+use std::path::Path;
 use std::ptr;
-use std::str;
 
-use anyhow::{anyhow, Result};
+use anyhow::anyhow;
 use gl::types::*;
 use nalgebra_glm as glm;
 
+use crate::shader_error::ShaderError;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Geometry,
+    Compute,
+}
+
+impl ShaderStage {
+    fn gl_type(self) -> GLenum {
+        match self {
+            Self::Vertex => gl::VERTEX_SHADER,
+            Self::Fragment => gl::FRAGMENT_SHADER,
+            Self::Geometry => gl::GEOMETRY_SHADER,
+            Self::Compute => gl::COMPUTE_SHADER,
+        }
+    }
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Vertex => "vertex",
+            Self::Fragment => "fragment",
+            Self::Geometry => "geometry",
+            Self::Compute => "compute",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 pub struct ShaderProgram {
     id: u32,
+    /// Texture unit of each active sampler uniform, with every element of a
+    /// sampler array listed by its own name, e.g. `textures[1]`.
+    sampler_units: HashMap<String, u32>,
 }
 
 impl ShaderProgram {
@@ -15,18 +57,67 @@ impl ShaderProgram {
         vertex_shader: &str,
         fragment_shader: &str,
         geometry_shader: Option<&str>,
-    ) -> Result<Self> {
-        let mut sources = vec![
-            (gl::VERTEX_SHADER, vertex_shader),
-            (gl::FRAGMENT_SHADER, fragment_shader),
-        ];
-        if let Some(g) = geometry_shader {
-            sources.push((gl::GEOMETRY_SHADER, g));
+    ) -> Result<Self, ShaderError> {
+        let sources = shader_sources(vertex_shader, fragment_shader, geometry_shader);
+        Self::compile(&sources, false)
+    }
+
+    /// Builds a program from a single compute shader. Requires a GL 4.3
+    /// context, see `capabilities::compute_shaders_supported`.
+    pub fn new_compute(compute_shader: &str) -> Result<Self, ShaderError> {
+        Self::compile(&[(ShaderStage::Compute, compute_shader)], false)
+    }
+
+    /// Like `new`, but stores the linked program binary in `cache_dir` and
+    /// reuses it on later runs. Binaries are keyed by the shader sources and
+    /// the GL driver, and the program is recompiled whenever the driver
+    /// rejects a cached binary or program binaries are unsupported.
+    pub fn new_cached<P>(
+        cache_dir: P,
+        vertex_shader: &str,
+        fragment_shader: &str,
+        geometry_shader: Option<&str>,
+    ) -> Result<Self, ShaderError>
+    where
+        P: AsRef<Path>,
+    {
+        let sources = shader_sources(vertex_shader, fragment_shader, geometry_shader);
+        if !program_binary_supported() {
+            return Self::compile(&sources, false);
+        }
+        let path = cache_dir
+            .as_ref()
+            .join(format!("{:016x}.bin", cache_key(&sources)));
+        if let Some(program) = Self::load_binary(&path) {
+            return Ok(program);
         }
+        let program = Self::compile(&sources, true)?;
+        if let Err(e) = program.save_binary(&path) {
+            eprintln!(
+                "Failed to write shader cache {}: {}",
+                path.to_string_lossy(),
+                e
+            );
+        }
+        Ok(program)
+    }
+
+    fn compile(sources: &[(ShaderStage, &str)], retrievable: bool) -> Result<Self, ShaderError> {
         let mut shaders = vec![];
-        for &(t, s) in sources.iter() {
-            let id = unsafe { gl::CreateShader(t) };
-            let source = CString::new(s).unwrap();
+        for &(stage, s) in sources.iter() {
+            let source = match CString::new(s) {
+                Ok(source) => source,
+                Err(e) => {
+                    for shader in shaders {
+                        unsafe { gl::DeleteShader(shader) };
+                    }
+                    return Err(ShaderError::InvalidSource {
+                        stage,
+                        position: e.nul_position(),
+                    });
+                }
+            };
+            let id = unsafe { gl::CreateShader(stage.gl_type()) };
             unsafe {
                 gl::ShaderSource(id, 1, &source.as_ptr(), ptr::null());
                 gl::CompileShader(id);
@@ -37,23 +128,12 @@ impl ShaderProgram {
                 gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success);
             }
             if success != gl::TRUE as GLint {
+                let log = unsafe { shader_info_log(id) };
+                unsafe { gl::DeleteShader(id) };
                 for shader in shaders {
                     unsafe { gl::DeleteShader(shader) };
                 }
-                let mut message = vec![0; 512];
-                unsafe {
-                    gl::GetShaderInfoLog(
-                        id,
-                        512,
-                        ptr::null_mut(),
-                        message.as_mut_ptr() as *mut GLchar,
-                    );
-                    message.set_len(message.iter().position(|&v| v == 0).unwrap_or(512));
-                }
-                return Err(anyhow!(
-                    "ERROR::SHADER::COMPILATION_FAILED:\n{}",
-                    str::from_utf8(&message).unwrap()
-                ));
+                return Err(ShaderError::compile(stage, s, &log));
             }
             shaders.push(id);
         }
@@ -63,6 +143,9 @@ impl ShaderProgram {
             unsafe { gl::AttachShader(id, shader) };
         }
         unsafe {
+            if retrievable {
+                gl::ProgramParameteri(id, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as GLint);
+            }
             gl::LinkProgram(id);
         }
         for shader in shaders {
@@ -73,28 +156,125 @@ impl ShaderProgram {
             gl::GetProgramiv(id, gl::LINK_STATUS, &mut success);
         }
         if success != gl::TRUE as GLint {
-            let mut message = vec![0; 512];
-            unsafe {
-                gl::GetProgramInfoLog(
-                    id,
-                    512,
-                    ptr::null_mut(),
-                    message.as_mut_ptr() as *mut GLchar,
-                );
-                message.set_len(message.iter().position(|&v| v == 0).unwrap_or(512));
+            let log = unsafe { program_info_log(id) };
+            unsafe { gl::DeleteProgram(id) };
+            return Err(ShaderError::link(&log));
+        }
+        Ok(unsafe { Self::from_linked(id) })
+    }
+
+    /// Gives each sampler uniform of a linked program a texture unit of its
+    /// own, in the order GL lists them, and points the uniform at it.
+    unsafe fn from_linked(id: u32) -> Self {
+        let mut count = 0;
+        let mut max_name_length = 0;
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORMS, &mut count);
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_name_length);
+        let mut previous_program = 0;
+        gl::GetIntegerv(gl::CURRENT_PROGRAM, &mut previous_program);
+        gl::UseProgram(id);
+        let mut sampler_units = HashMap::new();
+        for index in 0..count as u32 {
+            let mut name = vec![0u8; max_name_length.max(1) as usize];
+            let mut length = 0;
+            let mut size = 0;
+            let mut kind = 0;
+            gl::GetActiveUniform(
+                id,
+                index,
+                name.len() as GLsizei,
+                &mut length,
+                &mut size,
+                &mut kind,
+                name.as_mut_ptr() as *mut GLchar,
+            );
+            if !is_sampler(kind) {
+                continue;
             }
-            return Err(anyhow!(
-                "ERROR::SHADER::PROGRAM::LINKING_FAILED:\n{}",
-                str::from_utf8(&message).unwrap()
-            ));
+            name.truncate(length as usize);
+            let name = String::from_utf8_lossy(&name);
+            // Arrays are listed once, by the name of their first element.
+            let names: Vec<String> = match name.strip_suffix("[0]") {
+                Some(base) => (0..size).map(|i| format!("{}[{}]", base, i)).collect(),
+                None => vec![name.into_owned()],
+            };
+            for name in names {
+                let unit = sampler_units.len() as u32;
+                let location =
+                    gl::GetUniformLocation(id, CString::new(name.as_str()).unwrap().as_ptr());
+                gl::Uniform1i(location, unit as i32);
+                sampler_units.insert(name, unit);
+            }
+        }
+        gl::UseProgram(previous_program as u32);
+        Self { id, sampler_units }
+    }
+
+    fn load_binary(path: &Path) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        if data.len() < 4 {
+            return None;
+        }
+        let (format, binary) = data.split_at(4);
+        let format = u32::from_le_bytes([format[0], format[1], format[2], format[3]]);
+        let id = unsafe { gl::CreateProgram() };
+        let mut success = gl::FALSE as GLint;
+        unsafe {
+            gl::ProgramBinary(
+                id,
+                format,
+                binary.as_ptr() as *const c_void,
+                binary.len() as GLsizei,
+            );
+            gl::GetProgramiv(id, gl::LINK_STATUS, &mut success);
+        }
+        if success != gl::TRUE as GLint {
+            unsafe { gl::DeleteProgram(id) };
+            return None;
+        }
+        Some(unsafe { Self::from_linked(id) })
+    }
+
+    fn save_binary(&self, path: &Path) -> anyhow::Result<()> {
+        let mut length = 0;
+        unsafe {
+            gl::GetProgramiv(self.id, gl::PROGRAM_BINARY_LENGTH, &mut length);
+        }
+        if length <= 0 {
+            return Err(anyhow!("program binary is not retrievable"));
+        }
+        let mut binary = vec![0u8; length as usize];
+        let mut format = 0;
+        unsafe {
+            gl::GetProgramBinary(
+                self.id,
+                length,
+                &mut length,
+                &mut format,
+                binary.as_mut_ptr() as *mut c_void,
+            );
+            binary.set_len(length as usize);
         }
-        Ok(Self { id })
+        let mut data = Vec::with_capacity(4 + binary.len());
+        data.extend_from_slice(&format.to_le_bytes());
+        data.extend_from_slice(&binary);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, data)?;
+        Ok(())
     }
 
     pub fn id(&self) -> u32 {
         self.id
     }
 
+    /// The texture unit the sampler uniform `name` reads from, or `None` if
+    /// the program has no such active sampler.
+    pub fn sampler_unit(&self, name: &str) -> Option<u32> {
+        self.sampler_units.get(name).copied()
+    }
+
     pub unsafe fn use_program(&self) {
         gl::UseProgram(self.id);
     }
@@ -109,11 +289,21 @@ impl ShaderProgram {
         gl::Uniform1i(location, value);
     }
 
+    pub unsafe fn set_uniform_uint(&self, name: &str, value: u32) {
+        let location = self.get_uniform_location(name);
+        gl::Uniform1ui(location, value);
+    }
+
     pub unsafe fn set_uniform_float(&self, name: &str, value: f32) {
         let location = self.get_uniform_location(name);
         gl::Uniform1f(location, value);
     }
 
+    pub unsafe fn set_uniform_vec2f(&self, name: &str, value: glm::Vec2) {
+        let location = self.get_uniform_location(name);
+        gl::Uniform2fv(location, 1, value.as_ptr());
+    }
+
     pub unsafe fn set_uniform_vec3f(&self, name: &str, value: glm::Vec3) {
         let location = self.get_uniform_location(name);
         gl::Uniform3fv(location, 1, value.as_ptr());
@@ -128,3 +318,158 @@ impl ShaderProgram {
         gl::GetUniformLocation(self.id, CString::new(name).unwrap().as_ptr())
     }
 }
+
+/// Whether a uniform of type `kind` is a sampler, of any dimensionality,
+/// sample type or comparison mode.
+fn is_sampler(kind: GLenum) -> bool {
+    matches!(
+        kind,
+        gl::SAMPLER_1D
+            | gl::SAMPLER_2D
+            | gl::SAMPLER_3D
+            | gl::SAMPLER_CUBE
+            | gl::SAMPLER_1D_ARRAY
+            | gl::SAMPLER_2D_ARRAY
+            | gl::SAMPLER_2D_MULTISAMPLE
+            | gl::SAMPLER_2D_MULTISAMPLE_ARRAY
+            | gl::SAMPLER_BUFFER
+            | gl::SAMPLER_2D_RECT
+            | gl::SAMPLER_CUBE_MAP_ARRAY
+            | gl::SAMPLER_1D_SHADOW
+            | gl::SAMPLER_2D_SHADOW
+            | gl::SAMPLER_1D_ARRAY_SHADOW
+            | gl::SAMPLER_2D_ARRAY_SHADOW
+            | gl::SAMPLER_CUBE_SHADOW
+            | gl::SAMPLER_2D_RECT_SHADOW
+            | gl::SAMPLER_CUBE_MAP_ARRAY_SHADOW
+            | gl::INT_SAMPLER_1D
+            | gl::INT_SAMPLER_2D
+            | gl::INT_SAMPLER_3D
+            | gl::INT_SAMPLER_CUBE
+            | gl::INT_SAMPLER_1D_ARRAY
+            | gl::INT_SAMPLER_2D_ARRAY
+            | gl::INT_SAMPLER_2D_MULTISAMPLE
+            | gl::INT_SAMPLER_2D_MULTISAMPLE_ARRAY
+            | gl::INT_SAMPLER_BUFFER
+            | gl::INT_SAMPLER_2D_RECT
+            | gl::INT_SAMPLER_CUBE_MAP_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_1D
+            | gl::UNSIGNED_INT_SAMPLER_2D
+            | gl::UNSIGNED_INT_SAMPLER_3D
+            | gl::UNSIGNED_INT_SAMPLER_CUBE
+            | gl::UNSIGNED_INT_SAMPLER_1D_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_2D_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_2D_MULTISAMPLE
+            | gl::UNSIGNED_INT_SAMPLER_2D_MULTISAMPLE_ARRAY
+            | gl::UNSIGNED_INT_SAMPLER_BUFFER
+            | gl::UNSIGNED_INT_SAMPLER_2D_RECT
+            | gl::UNSIGNED_INT_SAMPLER_CUBE_MAP_ARRAY
+    )
+}
+
+fn shader_sources<'a>(
+    vertex_shader: &'a str,
+    fragment_shader: &'a str,
+    geometry_shader: Option<&'a str>,
+) -> Vec<(ShaderStage, &'a str)> {
+    let mut sources = vec![
+        (ShaderStage::Vertex, vertex_shader),
+        (ShaderStage::Fragment, fragment_shader),
+    ];
+    if let Some(g) = geometry_shader {
+        sources.push((ShaderStage::Geometry, g));
+    }
+    sources
+}
+
+unsafe fn shader_info_log(shader: u32) -> String {
+    let mut length = 0;
+    gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut length);
+    let mut log = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    gl::GetShaderInfoLog(
+        shader,
+        log.len() as GLsizei,
+        &mut written,
+        log.as_mut_ptr() as *mut GLchar,
+    );
+    log.truncate(written as usize);
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+unsafe fn program_info_log(program: u32) -> String {
+    let mut length = 0;
+    gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut length);
+    let mut log = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    gl::GetProgramInfoLog(
+        program,
+        log.len() as GLsizei,
+        &mut written,
+        log.as_mut_ptr() as *mut GLchar,
+    );
+    log.truncate(written as usize);
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+fn program_binary_supported() -> bool {
+    if !gl::GetProgramBinary::is_loaded()
+        || !gl::ProgramBinary::is_loaded()
+        || !gl::ProgramParameteri::is_loaded()
+    {
+        return false;
+    }
+    let mut format_count = 0;
+    unsafe {
+        gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut format_count);
+    }
+    format_count > 0
+}
+
+/// Hashes the shader sources together with the driver identification so that a
+/// driver update invalidates previously cached binaries. The hash is FNV-1a,
+/// which unlike the standard library's hashers stays the same across Rust
+/// releases, so cached binaries outlive toolchain updates.
+fn cache_key(sources: &[(ShaderStage, &str)]) -> u64 {
+    let mut key = FNV_OFFSET_BASIS;
+    for &name in [gl::VENDOR, gl::RENDERER, gl::VERSION].iter() {
+        let value = unsafe { gl::GetString(name) };
+        if !value.is_null() {
+            let value = unsafe { CStr::from_ptr(value as *const GLchar) };
+            key = fnv1a(key, value.to_bytes());
+        }
+        key = fnv1a(key, &[0]);
+    }
+    for (stage, source) in sources.iter() {
+        key = fnv1a(key, stage.to_string().as_bytes());
+        key = fnv1a(key, &[0]);
+        key = fnv1a(key, source.as_bytes());
+        key = fnv1a(key, &[0]);
+    }
+    key
+}
+
+/// Continues the 64-bit FNV-1a hash `hash` over `bytes`.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn fnv1a_continues_across_calls() {
+        let split = fnv1a(fnv1a(FNV_OFFSET_BASIS, b"foo"), b"bar");
+        assert_eq!(split, fnv1a(FNV_OFFSET_BASIS, b"foobar"));
+    }
+}
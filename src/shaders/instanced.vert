@@ -2,13 +2,15 @@
 layout (location = 0) in vec3 aPos;
 layout (location = 2) in vec2 aTexCoord;
 layout (location = 3) in mat4 model;
+layout (location = 7) in uint layer;
 
 out vec2 TexCoord;
+flat out uint Layer;
 
-uniform mat4 view;
-uniform mat4 projection;
+uniform mat4 viewProjection;
 
 void main() {
-    gl_Position = projection * view * model * vec4(aPos, 1.0);
+    gl_Position = viewProjection * model * vec4(aPos, 1.0);
     TexCoord = aTexCoord;
+    Layer = layer;
 }
@@ -0,0 +1,12 @@
+#version 330 core
+
+in vec2 TexCoord;
+flat in uint Layer;
+
+out vec4 FragColor;
+
+uniform sampler2DArray textures;
+
+void main() {
+    FragColor = texture(textures, vec3(TexCoord, float(Layer)));
+}
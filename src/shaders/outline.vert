@@ -0,0 +1,23 @@
+#version 330 core
+layout (location = 0) in vec3 aPos;
+layout (location = 1) in vec3 aNormal;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+// Outline width in pixels.
+uniform float thickness;
+uniform vec2 viewportSize;
+
+void main() {
+    mat4 viewModel = view * model;
+    vec4 position = projection * viewModel * vec4(aPos, 1.0);
+    // Push the vertex outwards along its normal as seen on screen. Scaling by w
+    // undoes the perspective divide, so the width is the same at any distance.
+    vec3 normal = mat3(transpose(inverse(viewModel))) * aNormal;
+    vec2 offset = (projection * vec4(normal, 0.0)).xy;
+    if (length(offset) > 0.0) {
+        position.xy += normalize(offset) * thickness * 2.0 / viewportSize * position.w;
+    }
+    gl_Position = position;
+}
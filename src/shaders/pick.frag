@@ -0,0 +1,12 @@
+#version 330 core
+
+flat in uint Instance;
+
+out uvec4 Id;
+
+uniform uint object;
+uniform uint mesh;
+
+void main() {
+    Id = uvec4(object, mesh, Instance, 0u);
+}
@@ -0,0 +1,21 @@
+#version 330 core
+
+in vec2 TexCoord;
+
+out vec4 FragColor;
+
+struct Material {
+    sampler2D texture_diffuse[1];
+};
+
+uniform Material material;
+// Tiling noise around 1 that breaks up the diffuse texture up close.
+uniform sampler2D detail;
+
+const float DETAIL_SCALE = 16.0;
+
+void main() {
+    vec4 color = texture(material.texture_diffuse[0], TexCoord);
+    float detail = texture(detail, TexCoord * DETAIL_SCALE).r;
+    FragColor = vec4(color.rgb * mix(0.8, 1.2, detail), color.a);
+}
@@ -1,12 +1,521 @@
 use std::ffi::c_void;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::BufReader;
 use std::path::Path;
+use std::ptr;
 
-use anyhow::Result;
-use image::GenericImageView;
+use anyhow::{anyhow, Result};
+use gl::types::*;
+use image::hdr::HdrDecoder;
+use image::{DynamicImage, GenericImageView};
+use nalgebra_glm as glm;
+
+use crate::bcn;
+use crate::capabilities;
+use crate::compressed_texture::CompressedImage;
+
+// From GL_EXT_texture_filter_anisotropic, core in GL 4.6 but missing from the
+// bindings.
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+/// Channels stored in the texture. Images are expanded to RGBA and the first
+/// channels kept, so `R` holds the red or gray value and a missing alpha
+/// channel reads as opaque.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channels {
+    R,
+    Rg,
+    Rgb,
+    Rgba,
+}
+
+/// Storage per channel. `F16` and `F32` are floats; the others are normalized
+/// integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Precision {
+    U8,
+    U16,
+    F16,
+    F32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+/// How an image is stored and sampled. The defaults match what textures have
+/// always used: linear RGBA at the precision of the image, trilinear filtering
+/// and repeating edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureOptions {
+    srgb: bool,
+    channels: Channels,
+    /// `None` keeps the precision of the image.
+    precision: Option<Precision>,
+    min_filter: Filter,
+    mag_filter: Filter,
+    /// Filter between mipmap levels, or `None` to not generate mipmaps.
+    mipmap_filter: Option<Filter>,
+    anisotropy: f32,
+    wrap_s: GLenum,
+    wrap_t: GLenum,
+    border_color: Option<glm::Vec4>,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            srgb: false,
+            channels: Channels::Rgba,
+            precision: None,
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mipmap_filter: Some(Filter::Linear),
+            anisotropy: 1.0,
+            wrap_s: gl::REPEAT,
+            wrap_t: gl::REPEAT,
+            border_color: None,
+        }
+    }
+}
+
+// The float fields never hold NaN or -0.0, see the builders, so equality is
+// total and they can be hashed by their bits.
+impl Eq for TextureOptions {}
+
+impl Hash for TextureOptions {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.srgb.hash(state);
+        self.channels.hash(state);
+        self.precision.hash(state);
+        self.min_filter.hash(state);
+        self.mag_filter.hash(state);
+        self.mipmap_filter.hash(state);
+        self.anisotropy.to_bits().hash(state);
+        self.wrap_s.hash(state);
+        self.wrap_t.hash(state);
+        if let Some(color) = self.border_color {
+            for component in color.iter() {
+                component.to_bits().hash(state);
+            }
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Stores colors as sRGB so they are converted to linear when sampled.
+    /// Meant for color maps like diffuse textures, not for data like normal or
+    /// specular maps. Requires 8-bit RGB or RGBA.
+    pub fn srgb(mut self) -> Self {
+        self.srgb = true;
+        self
+    }
+
+    pub fn channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    pub fn filter(mut self, min_filter: Filter, mag_filter: Filter) -> Self {
+        self.min_filter = min_filter;
+        self.mag_filter = mag_filter;
+        self
+    }
+
+    /// Sets the filter between mipmap levels, or turns mipmaps off with `None`.
+    pub fn mipmaps(mut self, mipmap_filter: Option<Filter>) -> Self {
+        self.mipmap_filter = mipmap_filter;
+        self
+    }
+
+    /// Sets the maximum anisotropy. It is clamped to what the driver supports
+    /// and ignored without anisotropic filtering. Values below 1, and NaN,
+    /// turn it off, so options that sample alike also hash alike.
+    pub fn anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = anisotropy.max(1.0);
+        self
+    }
+
+    pub fn wrap(mut self, wrap_s: GLenum, wrap_t: GLenum) -> Self {
+        self.wrap_s = wrap_s;
+        self.wrap_t = wrap_t;
+        self
+    }
+
+    /// Sets the color sampled outside the texture with `CLAMP_TO_BORDER`.
+    /// NaN components are taken as 0.
+    pub fn border_color(mut self, color: glm::Vec4) -> Self {
+        // Adding 0 turns -0 into 0.
+        self.border_color = Some(color.map(|c| if c.is_nan() { 0.0 } else { c + 0.0 }));
+        self
+    }
+
+    fn min_filter(&self) -> GLenum {
+        match (self.min_filter, self.mipmap_filter) {
+            (Filter::Nearest, None) => gl::NEAREST,
+            (Filter::Linear, None) => gl::LINEAR,
+            (Filter::Nearest, Some(Filter::Nearest)) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Nearest, Some(Filter::Linear)) => gl::NEAREST_MIPMAP_LINEAR,
+            (Filter::Linear, Some(Filter::Nearest)) => gl::LINEAR_MIPMAP_NEAREST,
+            (Filter::Linear, Some(Filter::Linear)) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    /// Sets the wrapping, filtering and anisotropy of the texture bound to
+    /// `target`.
+    unsafe fn apply_sampling(&self, target: GLenum) {
+        self.set_sampling_parameters(SamplingParameters::Texture(target));
+    }
+
+    /// Sets the wrapping, filtering and anisotropy of a sampler object. The
+    /// other options only concern how textures are stored and are ignored.
+    pub unsafe fn apply_to_sampler(&self, sampler: u32) {
+        self.set_sampling_parameters(SamplingParameters::Sampler(sampler));
+    }
+
+    unsafe fn set_sampling_parameters(&self, parameters: SamplingParameters) {
+        parameters.set_int(gl::TEXTURE_WRAP_S, self.wrap_s as i32);
+        parameters.set_int(gl::TEXTURE_WRAP_T, self.wrap_t as i32);
+        parameters.set_int(gl::TEXTURE_MIN_FILTER, self.min_filter() as i32);
+        let mag_filter = match self.mag_filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear => gl::LINEAR,
+        };
+        parameters.set_int(gl::TEXTURE_MAG_FILTER, mag_filter as i32);
+        if let Some(color) = self.border_color {
+            parameters.set_floats(gl::TEXTURE_BORDER_COLOR, color.as_ptr());
+        }
+        if self.anisotropy > 1.0 && capabilities::anisotropic_filtering_supported() {
+            let mut max_anisotropy = 1.0;
+            gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max_anisotropy);
+            let anisotropy = self.anisotropy.min(max_anisotropy);
+            parameters.set_float(TEXTURE_MAX_ANISOTROPY, anisotropy);
+        }
+    }
+
+    fn internal_format(&self, precision: Precision) -> Result<GLenum> {
+        use Channels::*;
+        use Precision::*;
+        if self.srgb {
+            return match (self.channels, precision) {
+                (Rgb, U8) => Ok(gl::SRGB8),
+                (Rgba, U8) => Ok(gl::SRGB8_ALPHA8),
+                (channels, precision) => Err(anyhow!(
+                    "sRGB textures must be 8-bit RGB or RGBA, not {:?} {:?}",
+                    precision,
+                    channels
+                )),
+            };
+        }
+        let format = match (self.channels, precision) {
+            (R, U8) => gl::R8,
+            (Rg, U8) => gl::RG8,
+            (Rgb, U8) => gl::RGB8,
+            (Rgba, U8) => gl::RGBA8,
+            (R, U16) => gl::R16,
+            (Rg, U16) => gl::RG16,
+            (Rgb, U16) => gl::RGB16,
+            (Rgba, U16) => gl::RGBA16,
+            (R, F16) => gl::R16F,
+            (Rg, F16) => gl::RG16F,
+            (Rgb, F16) => gl::RGB16F,
+            (Rgba, F16) => gl::RGBA16F,
+            (R, F32) => gl::R32F,
+            (Rg, F32) => gl::RG32F,
+            (Rgb, F32) => gl::RGB32F,
+            (Rgba, F32) => gl::RGBA32F,
+        };
+        Ok(format)
+    }
+}
+
+/// Where sampling parameters are set: on the texture bound to a target, or on
+/// a sampler object.
+enum SamplingParameters {
+    Texture(GLenum),
+    Sampler(u32),
+}
+
+impl SamplingParameters {
+    unsafe fn set_int(&self, name: GLenum, value: i32) {
+        match *self {
+            Self::Texture(target) => gl::TexParameteri(target, name, value),
+            Self::Sampler(sampler) => gl::SamplerParameteri(sampler, name, value),
+        }
+    }
+
+    unsafe fn set_float(&self, name: GLenum, value: f32) {
+        match *self {
+            Self::Texture(target) => gl::TexParameterf(target, name, value),
+            Self::Sampler(sampler) => gl::SamplerParameterf(sampler, name, value),
+        }
+    }
+
+    unsafe fn set_floats(&self, name: GLenum, values: *const f32) {
+        match *self {
+            Self::Texture(target) => gl::TexParameterfv(target, name, values),
+            Self::Sampler(sampler) => gl::SamplerParameterfv(sampler, name, values),
+        }
+    }
+}
+
+/// Decoded pixel data, `channels` samples per pixel.
+#[derive(Clone)]
+enum Samples {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+impl Samples {
+    fn size(&self) -> usize {
+        match self {
+            Samples::U8(raw) => raw.len(),
+            Samples::U16(raw) => raw.len() * 2,
+            Samples::F32(raw) => raw.len() * 4,
+        }
+    }
+
+    fn data_type(&self) -> GLenum {
+        match self {
+            Samples::U8(_) => gl::UNSIGNED_BYTE,
+            Samples::U16(_) => gl::UNSIGNED_SHORT,
+            Samples::F32(_) => gl::FLOAT,
+        }
+    }
+
+    fn as_ptr(&self) -> *const c_void {
+        match self {
+            Samples::U8(raw) => raw.as_ptr() as *const c_void,
+            Samples::U16(raw) => raw.as_ptr() as *const c_void,
+            Samples::F32(raw) => raw.as_ptr() as *const c_void,
+        }
+    }
+}
+
+enum Pixels {
+    Uncompressed {
+        internal_format: GLenum,
+        format: GLenum,
+        samples: Samples,
+    },
+    Compressed(CompressedImage),
+    /// A block compressed image in a format the driver cannot sample, with
+    /// its levels decompressed to the format's `fallback_format`.
+    Decompressed(CompressedImage),
+}
+
+/// An image decoded and converted as its options ask, ready to be uploaded.
+/// Creating one needs no OpenGL context, so it can happen on any thread.
+pub struct TextureData {
+    width: u32,
+    height: u32,
+    pixels: Pixels,
+    options: TextureOptions,
+}
+
+impl TextureData {
+    /// Decodes an image file. Radiance `.hdr` images decode to 32-bit floats.
+    /// DDS, KTX and KTX2 files hold block compressed images that keep their
+    /// format and mipmaps, so only the sRGB and sampling options apply to them.
+    /// Those the driver cannot sample are decompressed here rather than on
+    /// upload, see `compressed_texture::detect_supported_formats`.
+    pub fn decode<P>(path: P, options: &TextureOptions) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let extension = extension.map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("dds" | "ktx" | "ktx2") => {
+                let mut image = CompressedImage::load(path)?;
+                if options.srgb {
+                    image.format = image
+                        .format
+                        .srgb()
+                        .ok_or_else(|| anyhow!("{:?} textures cannot be sRGB", image.format))?;
+                }
+                let (width, height) = (image.width, image.height);
+                let pixels = if image.format.supported() {
+                    Pixels::Compressed(image)
+                } else {
+                    eprintln!(
+                        "{:?} textures are not supported by the driver, decompressing {}",
+                        image.format,
+                        path.display()
+                    );
+                    Pixels::Decompressed(decompress(image, options))
+                };
+                Ok(Self {
+                    width,
+                    height,
+                    pixels,
+                    options: *options,
+                })
+            }
+            Some("hdr") => {
+                let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+                let metadata = decoder.metadata();
+                let pixels = decoder.read_image_hdr()?;
+                let raw = pixels.iter().flat_map(|pixel| pixel.0.to_vec()).collect();
+                Self::new(
+                    metadata.width,
+                    metadata.height,
+                    Samples::F32(raw),
+                    3,
+                    options,
+                )
+            }
+            _ => {
+                let image = image::open(path)?;
+                let (width, height) = image.dimensions();
+                let (samples, channels) = decode(image);
+                Self::new(width, height, samples, channels, options)
+            }
+        }
+    }
+
+    /// Creates texture data from 8-bit RGBA pixels, row by row from the
+    /// bottom.
+    pub fn from_rgba8(
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        Self::new(width, height, Samples::U8(pixels), 4, options)
+    }
+
+    fn new(
+        width: u32,
+        height: u32,
+        samples: Samples,
+        channels: usize,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        let target_channels = match options.channels {
+            Channels::R => 1,
+            Channels::Rg => 2,
+            Channels::Rgb => 3,
+            Channels::Rgba => 4,
+        };
+        let samples = match samples {
+            Samples::U8(raw) => Samples::U8(remap(&raw, channels, target_channels, u8::MAX)),
+            Samples::U16(raw) => Samples::U16(remap(&raw, channels, target_channels, u16::MAX)),
+            Samples::F32(raw) => Samples::F32(remap(&raw, channels, target_channels, 1.0)),
+        };
+        let precision = options.precision.unwrap_or(match samples {
+            Samples::U8(_) => Precision::U8,
+            Samples::U16(_) => Precision::U16,
+            Samples::F32(_) => Precision::F32,
+        });
+        let samples = convert(samples, precision);
+        let internal_format = options.internal_format(precision)?;
+        let format = match options.channels {
+            Channels::R => gl::RED,
+            Channels::Rg => gl::RG,
+            Channels::Rgb => gl::RGB,
+            Channels::Rgba => gl::RGBA,
+        };
+        Ok(Self {
+            width,
+            height,
+            pixels: Pixels::Uncompressed {
+                internal_format,
+                format,
+                samples,
+            },
+            options: *options,
+        })
+    }
+
+    /// Returns a copy with the color channels multiplied by `tint`. Block
+    /// compressed images cannot be tinted.
+    pub fn tinted(&self, tint: glm::Vec3) -> Result<Self> {
+        let (internal_format, format, samples) = match &self.pixels {
+            Pixels::Uncompressed {
+                internal_format,
+                format,
+                samples,
+            } => (*internal_format, *format, samples),
+            Pixels::Compressed(image) | Pixels::Decompressed(image) => {
+                return Err(anyhow!("{:?} textures cannot be tinted", image.format))
+            }
+        };
+        let channels = match format {
+            gl::RED => 1,
+            gl::RG => 2,
+            gl::RGB => 3,
+            _ => 4,
+        };
+        let factor = |i: usize| match i % channels {
+            c if c < 3 => tint[c],
+            _ => 1.0,
+        };
+        let samples = match samples {
+            Samples::U8(raw) => Samples::U8(
+                raw.iter()
+                    .enumerate()
+                    .map(|(i, &v)| (v as f32 * factor(i)).round().min(255.0) as u8)
+                    .collect(),
+            ),
+            Samples::U16(raw) => Samples::U16(
+                raw.iter()
+                    .enumerate()
+                    .map(|(i, &v)| (v as f32 * factor(i)).round().min(65535.0) as u16)
+                    .collect(),
+            ),
+            Samples::F32(raw) => Samples::F32(
+                raw.iter()
+                    .enumerate()
+                    .map(|(i, &v)| v * factor(i))
+                    .collect(),
+            ),
+        };
+        Ok(Self {
+            width: self.width,
+            height: self.height,
+            pixels: Pixels::Uncompressed {
+                internal_format,
+                format,
+                samples,
+            },
+            options: self.options,
+        })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Bytes of pixel data to upload.
+    pub fn size(&self) -> usize {
+        match &self.pixels {
+            Pixels::Uncompressed { samples, .. } => samples.size(),
+            Pixels::Compressed(image) | Pixels::Decompressed(image) => {
+                image.levels.iter().map(Vec::len).sum()
+            }
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Texture {
     id: u32,
+    /// `TEXTURE_2D`, or `TEXTURE_2D_ARRAY` for textures made with
+    /// `upload_array`.
+    target: GLenum,
+    layers: u32,
 }
 
 impl Texture {
@@ -14,49 +523,160 @@ impl Texture {
     where
         P: AsRef<Path>,
     {
-        let image = image::open(path)?;
-        let (width, height) = image.dimensions();
-        let raw = image.into_rgba().into_raw();
+        Self::load_with(path, &TextureOptions::default())
+    }
+
+    /// Loads an image with the given options, as described for
+    /// `TextureData::decode`.
+    pub unsafe fn load_with<P>(path: P, options: &TextureOptions) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::upload(&TextureData::decode(path, options)?))
+    }
 
+    /// Uploads `data` as a new texture. Whatever was bound to the active unit
+    /// is bound again afterwards, see `TextureUnits`.
+    pub unsafe fn upload(data: &TextureData) -> Self {
+        let previous = bound_texture(gl::TEXTURE_BINDING_2D);
         let mut id = 0;
         gl::GenTextures(1, &mut id);
         gl::BindTexture(gl::TEXTURE_2D, id);
-        gl::TexImage2D(
-            gl::TEXTURE_2D,
+        // Rows of one or three byte pixels are not always 4-byte aligned.
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        match &data.pixels {
+            Pixels::Uncompressed {
+                internal_format,
+                format,
+                samples,
+            } => {
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    *internal_format as i32,
+                    data.width as i32,
+                    data.height as i32,
+                    0,
+                    *format,
+                    samples.data_type(),
+                    samples.as_ptr(),
+                );
+                if data.options.mipmap_filter.is_some() {
+                    gl::GenerateMipmap(gl::TEXTURE_2D);
+                }
+            }
+            Pixels::Compressed(image) => upload_compressed(image, &data.options),
+            Pixels::Decompressed(image) => upload_decompressed(image),
+        }
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        data.options.apply_sampling(gl::TEXTURE_2D);
+        gl::BindTexture(gl::TEXTURE_2D, previous);
+
+        Self {
+            id,
+            target: gl::TEXTURE_2D,
+            layers: 1,
+        }
+    }
+
+    /// Uploads images of the same size, format and options as the layers of
+    /// a `TEXTURE_2D_ARRAY`, so that draws can pick between them without
+    /// rebinding. Block compressed images are not supported.
+    pub unsafe fn upload_array(layers: &[TextureData]) -> Result<Self> {
+        let first = layers
+            .first()
+            .ok_or_else(|| anyhow!("Texture arrays need at least one layer"))?;
+        let (internal_format, format, data_type) = match &first.pixels {
+            Pixels::Uncompressed {
+                internal_format,
+                format,
+                samples,
+            } => (*internal_format, *format, samples.data_type()),
+            Pixels::Compressed(image) | Pixels::Decompressed(image) => {
+                return Err(anyhow!(
+                    "{:?} textures cannot be used in texture arrays",
+                    image.format
+                ))
+            }
+        };
+        let matches = |layer: &TextureData| match &layer.pixels {
+            Pixels::Uncompressed {
+                internal_format: layer_internal_format,
+                samples,
+                ..
+            } => {
+                (layer.width, layer.height) == (first.width, first.height)
+                    && *layer_internal_format == internal_format
+                    && samples.data_type() == data_type
+                    && layer.options == first.options
+            }
+            Pixels::Compressed(_) | Pixels::Decompressed(_) => false,
+        };
+        if !layers.iter().all(matches) {
+            return Err(anyhow!(
+                "Texture array layers must share their size, format and options"
+            ));
+        }
+
+        let previous = bound_texture(gl::TEXTURE_BINDING_2D_ARRAY);
+        let mut id = 0;
+        gl::GenTextures(1, &mut id);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, id);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage3D(
+            gl::TEXTURE_2D_ARRAY,
             0,
-            gl::RGBA8 as i32,
-            width as i32,
-            height as i32,
+            internal_format as i32,
+            first.width as i32,
+            first.height as i32,
+            layers.len() as i32,
             0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            raw.as_ptr() as *const c_void,
+            format,
+            data_type,
+            ptr::null(),
         );
-        gl::GenerateMipmap(gl::TEXTURE_2D);
-
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-        gl::TexParameteri(
-            gl::TEXTURE_2D,
-            gl::TEXTURE_MIN_FILTER,
-            gl::LINEAR_MIPMAP_LINEAR as i32,
-        );
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        for (index, layer) in layers.iter().enumerate() {
+            if let Pixels::Uncompressed { samples, .. } = &layer.pixels {
+                gl::TexSubImage3D(
+                    gl::TEXTURE_2D_ARRAY,
+                    0,
+                    0,
+                    0,
+                    index as i32,
+                    first.width as i32,
+                    first.height as i32,
+                    1,
+                    format,
+                    data_type,
+                    samples.as_ptr(),
+                );
+            }
+        }
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        if first.options.mipmap_filter.is_some() {
+            gl::GenerateMipmap(gl::TEXTURE_2D_ARRAY);
+        }
+        first.options.apply_sampling(gl::TEXTURE_2D_ARRAY);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, previous);
 
-        gl::BindTexture(gl::TEXTURE_2D, 0);
+        Ok(Self {
+            id,
+            target: gl::TEXTURE_2D_ARRAY,
+            layers: layers.len() as u32,
+        })
+    }
 
-        Ok(Self { id })
+    /// The number of layers, which is 1 unless this is a texture array.
+    pub fn layers(&self) -> u32 {
+        self.layers
     }
 
-    pub unsafe fn set_wrap(&self, wrap_s: gl::types::GLenum, wrap_t: gl::types::GLenum) {
-        gl::BindTexture(gl::TEXTURE_2D, self.id);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap_s as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap_t as i32);
-        gl::BindTexture(gl::TEXTURE_2D, 0);
+    pub fn target(&self) -> GLenum {
+        self.target
     }
 
     pub unsafe fn bind(&self) {
-        gl::BindTexture(gl::TEXTURE_2D, self.id);
+        gl::BindTexture(self.target, self.id);
     }
 }
 
@@ -67,3 +687,223 @@ impl Drop for Texture {
         }
     }
 }
+
+/// The texture bound to the active unit, queried with a `TEXTURE_BINDING_*`
+/// name.
+unsafe fn bound_texture(binding: GLenum) -> u32 {
+    let mut id = 0;
+    gl::GetIntegerv(binding, &mut id);
+    id as u32
+}
+
+/// Returns the samples of an image and the number of channels per pixel,
+/// which are gray or gray and alpha for one or two channels.
+fn decode(image: DynamicImage) -> (Samples, usize) {
+    match image {
+        DynamicImage::ImageLuma8(image) => (Samples::U8(image.into_raw()), 1),
+        DynamicImage::ImageLumaA8(image) => (Samples::U8(image.into_raw()), 2),
+        DynamicImage::ImageRgb8(image) => (Samples::U8(image.into_raw()), 3),
+        DynamicImage::ImageRgba8(image) => (Samples::U8(image.into_raw()), 4),
+        image @ DynamicImage::ImageBgr8(_) => (Samples::U8(image.into_rgb().into_raw()), 3),
+        image @ DynamicImage::ImageBgra8(_) => (Samples::U8(image.into_rgba().into_raw()), 4),
+        DynamicImage::ImageLuma16(image) => (Samples::U16(image.into_raw()), 1),
+        DynamicImage::ImageLumaA16(image) => (Samples::U16(image.into_raw()), 2),
+        DynamicImage::ImageRgb16(image) => (Samples::U16(image.into_raw()), 3),
+        DynamicImage::ImageRgba16(image) => (Samples::U16(image.into_raw()), 4),
+    }
+}
+
+/// Converts pixels of `from` channels to `to` channels, reading them as RGBA
+/// with gray repeated across red, green and blue and `one` for missing alpha.
+fn remap<T: Copy>(raw: &[T], from: usize, to: usize, one: T) -> Vec<T> {
+    if from == to {
+        return raw.to_vec();
+    }
+    let mut remapped = Vec::with_capacity(raw.len() / from * to);
+    for pixel in raw.chunks_exact(from) {
+        for channel in 0..to {
+            let value = match (from, channel) {
+                (1, 0..=2) | (2, 0..=2) => pixel[0],
+                (2, 3) => pixel[1],
+                (1, 3) | (3, 3) => one,
+                _ => pixel[channel],
+            };
+            remapped.push(value);
+        }
+    }
+    remapped
+}
+
+/// Converts samples to the type uploaded for `precision`. Half floats are
+/// uploaded as 32-bit floats and converted by the driver.
+fn convert(samples: Samples, precision: Precision) -> Samples {
+    let to_unit = |value: f32| value.clamp(0.0, 1.0);
+    match (samples, precision) {
+        (Samples::U8(raw), Precision::U16) => {
+            Samples::U16(raw.into_iter().map(|v| v as u16 * 257).collect())
+        }
+        (Samples::U8(raw), Precision::F16) | (Samples::U8(raw), Precision::F32) => {
+            Samples::F32(raw.into_iter().map(|v| v as f32 / 255.0).collect())
+        }
+        (Samples::U16(raw), Precision::U8) => {
+            Samples::U8(raw.into_iter().map(|v| (v >> 8) as u8).collect())
+        }
+        (Samples::U16(raw), Precision::F16) | (Samples::U16(raw), Precision::F32) => {
+            Samples::F32(raw.into_iter().map(|v| v as f32 / 65535.0).collect())
+        }
+        (Samples::F32(raw), Precision::U8) => Samples::U8(
+            raw.into_iter()
+                .map(|v| (to_unit(v) * 255.0).round() as u8)
+                .collect(),
+        ),
+        (Samples::F32(raw), Precision::U16) => Samples::U16(
+            raw.into_iter()
+                .map(|v| (to_unit(v) * 65535.0).round() as u16)
+                .collect(),
+        ),
+        (samples, _) => samples,
+    }
+}
+
+/// Decompresses the levels of `image` that `options` will upload.
+fn decompress(image: CompressedImage, options: &TextureOptions) -> CompressedImage {
+    let levels = if options.mipmap_filter.is_some() {
+        image.levels.len()
+    } else {
+        1
+    };
+    let decompressed = (0..levels)
+        .map(|level| {
+            let (width, height) = image.level_size(level);
+            bcn::decompress(image.format, width, height, &image.levels[level])
+        })
+        .collect();
+    CompressedImage {
+        levels: decompressed,
+        ..image
+    }
+}
+
+/// Uploads a block compressed image to the bound texture as is.
+unsafe fn upload_compressed(image: &CompressedImage, options: &TextureOptions) {
+    let levels = if options.mipmap_filter.is_some() {
+        image.levels.len()
+    } else {
+        1
+    };
+    for (level, data) in image.levels.iter().take(levels).enumerate() {
+        let (width, height) = image.level_size(level);
+        gl::CompressedTexImage2D(
+            gl::TEXTURE_2D,
+            level as i32,
+            image.format.gl_format(),
+            width as i32,
+            height as i32,
+            0,
+            data.len() as i32,
+            data.as_ptr() as *const c_void,
+        );
+    }
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, levels as i32 - 1);
+}
+
+/// Uploads the levels of an image `decompress` made to the bound texture.
+unsafe fn upload_decompressed(image: &CompressedImage) {
+    let (internal_format, format, data_type, _) = image.format.fallback_format();
+    for (level, data) in image.levels.iter().enumerate() {
+        let (width, height) = image.level_size(level);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            level as i32,
+            internal_format as i32,
+            width as i32,
+            height as i32,
+            0,
+            format,
+            data_type,
+            data.as_ptr() as *const c_void,
+        );
+    }
+    gl::TexParameteri(
+        gl::TEXTURE_2D,
+        gl::TEXTURE_MAX_LEVEL,
+        image.levels.len() as i32 - 1,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_channels_and_precision_to_internal_formats() {
+        use Channels::*;
+        use Precision::*;
+        let expected = [
+            (R, U8, gl::R8),
+            (Rg, U8, gl::RG8),
+            (Rgb, U8, gl::RGB8),
+            (Rgba, U8, gl::RGBA8),
+            (R, U16, gl::R16),
+            (Rg, U16, gl::RG16),
+            (Rgb, U16, gl::RGB16),
+            (Rgba, U16, gl::RGBA16),
+            (R, F16, gl::R16F),
+            (Rg, F16, gl::RG16F),
+            (Rgb, F16, gl::RGB16F),
+            (Rgba, F16, gl::RGBA16F),
+            (R, F32, gl::R32F),
+            (Rg, F32, gl::RG32F),
+            (Rgb, F32, gl::RGB32F),
+            (Rgba, F32, gl::RGBA32F),
+        ];
+        for &(channels, precision, format) in expected.iter() {
+            let options = TextureOptions::default().channels(channels);
+            assert_eq!(
+                options.internal_format(precision).unwrap(),
+                format,
+                "{:?} {:?}",
+                channels,
+                precision
+            );
+        }
+    }
+
+    #[test]
+    fn maps_srgb_to_8_bit_color_formats_only() {
+        let options = TextureOptions::default().srgb();
+        assert_eq!(
+            options
+                .channels(Channels::Rgb)
+                .internal_format(Precision::U8)
+                .unwrap(),
+            gl::SRGB8
+        );
+        assert_eq!(
+            options.internal_format(Precision::U8).unwrap(),
+            gl::SRGB8_ALPHA8
+        );
+        assert!(options.internal_format(Precision::U16).is_err());
+        assert!(options.internal_format(Precision::F16).is_err());
+        assert!(options
+            .channels(Channels::Rg)
+            .internal_format(Precision::U8)
+            .is_err());
+    }
+
+    #[test]
+    fn normalizes_border_colors() {
+        let zero = TextureOptions::default().border_color(glm::vec4(0.0, 0.0, 0.0, 1.0));
+        let negative_zero = TextureOptions::default().border_color(glm::vec4(-0.0, 0.0, 0.0, 1.0));
+        let nan = TextureOptions::default().border_color(glm::vec4(f32::NAN, 0.0, 0.0, 1.0));
+        assert_eq!(zero, negative_zero);
+        assert_eq!(zero, nan);
+        let hash = |options: &TextureOptions| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            options.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&zero), hash(&negative_zero));
+        assert_eq!(hash(&zero), hash(&nan));
+    }
+}
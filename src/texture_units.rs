@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use gl::types::*;
+
+use crate::sampler::Sampler;
+use crate::shader_program::ShaderProgram;
+use crate::texture::Texture;
+
+/// Tracks what is bound to each texture unit so that draws sharing textures
+/// do not bind them again. A unit has a binding per texture target, so a
+/// `TEXTURE_2D` and a `TEXTURE_2D_ARRAY` on the same unit are tracked
+/// separately. Bound textures and samplers are kept alive until replaced, so
+/// a unit never refers to a deleted object.
+///
+/// Everything drawing with textures should bind them through here, or the
+/// tracked bindings go stale.
+pub struct TextureUnits {
+    unit_count: u32,
+    textures: Bindings<(u32, GLenum), Texture>,
+    samplers: Bindings<u32, Sampler>,
+    active: u32,
+}
+
+impl TextureUnits {
+    pub unsafe fn new() -> Self {
+        let mut max_units = 0;
+        gl::GetIntegerv(gl::MAX_TEXTURE_IMAGE_UNITS, &mut max_units);
+        gl::ActiveTexture(gl::TEXTURE0);
+        Self {
+            unit_count: max_units as u32,
+            textures: Bindings::default(),
+            samplers: Bindings::default(),
+            active: 0,
+        }
+    }
+
+    /// Binds `texture` to `unit` along with `sampler`, or with no sampler so
+    /// the texture's own parameters apply. Fails if the driver has no such
+    /// unit.
+    pub unsafe fn bind(
+        &mut self,
+        unit: u32,
+        texture: &Rc<Texture>,
+        sampler: Option<&Rc<Sampler>>,
+    ) -> Result<()> {
+        if unit >= self.unit_count {
+            return Err(anyhow!(
+                "Texture unit {} is out of range, the driver supports {}",
+                unit,
+                self.unit_count
+            ));
+        }
+        if self
+            .textures
+            .replace((unit, texture.target()), Some(texture))
+        {
+            if self.active != unit {
+                gl::ActiveTexture(gl::TEXTURE0 + unit);
+                self.active = unit;
+            }
+            texture.bind();
+        }
+        if self.samplers.replace(unit, sampler) {
+            match sampler {
+                Some(sampler) => sampler.bind(unit),
+                None => Sampler::unbind(unit),
+            }
+        }
+        Ok(())
+    }
+
+    /// Binds `texture` to the unit `shader` reads the sampler uniform `name`
+    /// from. Does nothing if the shader does not use that sampler.
+    pub unsafe fn bind_uniform(
+        &mut self,
+        shader: &ShaderProgram,
+        name: &str,
+        texture: &Rc<Texture>,
+        sampler: Option<&Rc<Sampler>>,
+    ) -> Result<()> {
+        match shader.sampler_unit(name) {
+            Some(unit) => self.bind(unit, texture, sampler),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The objects bound at each binding point, nothing being bound to points
+/// missing from the map.
+struct Bindings<K, T> {
+    bound: HashMap<K, Rc<T>>,
+}
+
+impl<K, T> Default for Bindings<K, T> {
+    fn default() -> Self {
+        Self {
+            bound: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, T> Bindings<K, T> {
+    /// Records `object` as bound at `point`. Returns whether that changes
+    /// anything, that is whether it needs to be bound.
+    fn replace(&mut self, point: K, object: Option<&Rc<T>>) -> bool {
+        let unchanged = match (self.bound.get(&point), object) {
+            (Some(current), Some(object)) => Rc::ptr_eq(current, object),
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            match object {
+                Some(object) => self.bound.insert(point, Rc::clone(object)),
+                None => self.bound.remove(&point),
+            };
+        }
+        !unchanged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_binding_what_is_already_bound() {
+        let mut bindings = Bindings::default();
+        let (a, b) = (Rc::new("a"), Rc::new("b"));
+        assert!(bindings.replace(0, Some(&a)));
+        assert!(!bindings.replace(0, Some(&a)));
+        assert!(!bindings.replace(0, Some(&Rc::clone(&a))));
+        assert!(bindings.replace(0, Some(&b)));
+        assert!(bindings.replace(1, Some(&b)));
+    }
+
+    #[test]
+    fn compares_objects_by_identity() {
+        let mut bindings = Bindings::default();
+        assert!(bindings.replace(0, Some(&Rc::new("a"))));
+        assert!(bindings.replace(0, Some(&Rc::new("a"))));
+    }
+
+    #[test]
+    fn tracks_unbinding() {
+        let mut bindings = Bindings::<u32, &str>::default();
+        assert!(!bindings.replace(0, None));
+        assert!(bindings.replace(0, Some(&Rc::new("a"))));
+        assert!(bindings.replace(0, None));
+        assert!(!bindings.replace(0, None));
+    }
+
+    #[test]
+    fn tracks_each_target_of_a_unit_separately() {
+        let mut bindings = Bindings::default();
+        let texture = Rc::new("texture");
+        assert!(bindings.replace((0, gl::TEXTURE_2D), Some(&texture)));
+        assert!(bindings.replace((0, gl::TEXTURE_2D_ARRAY), Some(&texture)));
+        assert!(!bindings.replace((0, gl::TEXTURE_2D), Some(&texture)));
+        assert!(!bindings.replace((0, gl::TEXTURE_2D_ARRAY), Some(&texture)));
+    }
+
+    #[test]
+    fn keeps_bound_objects_alive() {
+        let mut bindings = Bindings::default();
+        let object = Rc::new("a");
+        bindings.replace(0, Some(&object));
+        assert_eq!(Rc::strong_count(&object), 2);
+        bindings.replace(0, None);
+        assert_eq!(Rc::strong_count(&object), 1);
+    }
+}
@@ -0,0 +1,188 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use image::RgbImage;
+
+use crate::screenshot;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// Numbered PNG files in a directory.
+    Png,
+    /// A single raw YUV 4:2:0 stream, which ffmpeg and most players read.
+    Y4m,
+}
+
+enum Output {
+    Png(PathBuf),
+    Y4m(PathBuf, BufWriter<File>),
+}
+
+/// Records the frames drawn into the default framebuffer. Each frame
+/// advances the scene by exactly `delta_time`, however long it took to draw,
+/// so the result plays back smoothly at `frame_rate`.
+pub struct VideoRecorder {
+    output: Output,
+    width: u32,
+    height: u32,
+    frame_rate: u32,
+    frames: u32,
+}
+
+impl VideoRecorder {
+    /// Starts a recording in `dir`, named by the current time. Frames are
+    /// `width` by `height`, so the window should keep its size meanwhile.
+    pub fn new(
+        format: VideoFormat,
+        dir: &Path,
+        width: u32,
+        height: u32,
+        frame_rate: u32,
+    ) -> Result<Self> {
+        let name = format!("video_{}", screenshot::timestamp());
+        let output = match format {
+            VideoFormat::Png => {
+                let path = dir.join(name);
+                fs::create_dir_all(&path)?;
+                Output::Png(path)
+            }
+            VideoFormat::Y4m => {
+                fs::create_dir_all(dir)?;
+                let path = dir.join(name).with_extension("y4m");
+                let mut writer = BufWriter::new(File::create(&path)?);
+                write_y4m_header(&mut writer, width, height, frame_rate)?;
+                Output::Y4m(path, writer)
+            }
+        };
+        Ok(Self {
+            output,
+            width,
+            height,
+            frame_rate,
+            frames: 0,
+        })
+    }
+
+    /// The time between frames in seconds.
+    pub fn delta_time(&self) -> f32 {
+        1.0 / self.frame_rate as f32
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Appends the frame drawn so far, waiting for the GPU to finish it.
+    pub unsafe fn capture(&mut self) -> Result<()> {
+        let image = screenshot::read_pixels(0, self.width, self.height);
+        match &mut self.output {
+            Output::Png(dir) => {
+                let path = dir.join(format!("frame_{:06}.png", self.frames));
+                screenshot::write_png(&image, &path)?;
+            }
+            Output::Y4m(_, writer) => write_y4m_frame(writer, &image)?,
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Finishes writing and returns where the recording was saved.
+    pub fn finish(self) -> Result<PathBuf> {
+        match self.output {
+            Output::Png(dir) => Ok(dir),
+            Output::Y4m(path, mut writer) => {
+                writer.flush()?;
+                Ok(path)
+            }
+        }
+    }
+}
+
+/// Writes the stream header, which comes once before the frames.
+fn write_y4m_header<W: Write>(
+    writer: &mut W,
+    width: u32,
+    height: u32,
+    frame_rate: u32,
+) -> Result<()> {
+    // C420jpeg places the chroma samples between the pixels of each 2x2
+    // block, which matches averaging them in `write_y4m_frame`.
+    writeln!(
+        writer,
+        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg",
+        width, height, frame_rate
+    )?;
+    Ok(())
+}
+
+/// Writes `image` as BT.601 limited range Y'CbCr with the chroma of each 2x2
+/// block averaged.
+fn write_y4m_frame<W: Write>(writer: &mut W, image: &RgbImage) -> Result<()> {
+    let (width, height) = image.dimensions();
+    let rgb = |x: u32, y: u32| {
+        let pixel = image.get_pixel(x.min(width - 1), y.min(height - 1));
+        [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]
+    };
+    let luma: Vec<u8> = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b] = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+            (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8
+        })
+        .collect();
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut cb = Vec::with_capacity((chroma_width * chroma_height) as usize);
+    let mut cr = Vec::with_capacity(cb.capacity());
+    for y in 0..chroma_height {
+        for x in 0..chroma_width {
+            let mut sum = [0.0; 3];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                let pixel = rgb(2 * x + dx, 2 * y + dy);
+                for (total, value) in sum.iter_mut().zip(pixel.iter()) {
+                    *total += value / 4.0;
+                }
+            }
+            let [r, g, b] = sum;
+            cb.push((128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8);
+            cr.push((128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8);
+        }
+    }
+    writer.write_all(b"FRAME\n")?;
+    writer.write_all(&luma)?;
+    writer.write_all(&cb)?;
+    writer.write_all(&cr)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_stream_header() {
+        let mut out = vec![];
+        write_y4m_header(&mut out, 640, 480, 60).unwrap();
+        assert_eq!(out, b"YUV4MPEG2 W640 H480 F60:1 Ip A1:1 C420jpeg\n");
+    }
+
+    #[test]
+    fn writes_full_luma_and_quarter_chroma_planes() {
+        let mut out = vec![];
+        write_y4m_frame(&mut out, &RgbImage::new(4, 2)).unwrap();
+        assert!(out.starts_with(b"FRAME\n"));
+        assert_eq!(out.len(), 6 + 8 + 2 + 2);
+    }
+
+    #[test]
+    fn rounds_chroma_planes_up_for_odd_sizes() {
+        let mut out = vec![];
+        write_y4m_frame(&mut out, &RgbImage::new(3, 3)).unwrap();
+        assert!(out.starts_with(b"FRAME\n"));
+        assert_eq!(out.len(), 6 + 9 + 4 + 4);
+        // Black is 16 in limited range luma and 128 in chroma.
+        assert!(out[6..15].iter().all(|&y| y == 16));
+        assert!(out[15..].iter().all(|&c| c == 128));
+    }
+}